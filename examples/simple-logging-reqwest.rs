@@ -2,6 +2,7 @@
 async fn main() {
     use fenrir_rs::{Fenrir, NetworkingBackend, SerializationFormat};
     use log::{debug, error, info, set_boxed_logger, set_max_level, trace, warn, LevelFilter};
+    use std::time::Duration;
     use url::Url;
 
     let my_loki = Fenrir::builder()
@@ -12,6 +13,9 @@ async fn main() {
         .tag("service", "simple-logging")
         .build();
 
+    // the guard sends the remaining messages to Loki when the application exits
+    let _guard = my_loki.shutdown_guard(Duration::from_secs(5));
+
     // set the actual logger for the facade
     set_boxed_logger(Box::new(my_loki)).unwrap();
     set_max_level(LevelFilter::Trace);
@@ -1,5 +1,5 @@
 fn main() {
-    use fenrir_rs::{Fenrir, NetworkingBackend, SerializationFormat};
+    use fenrir_rs::{Fenrir, KeyValueMode, NetworkingBackend, SerializationFormat};
     use log::{debug, error, info, set_boxed_logger, set_max_level, trace, warn, LevelFilter};
     use url::Url;
 
@@ -9,6 +9,8 @@ fn main() {
         .format(SerializationFormat::Json)
         .include_level()
         .tag("service", "structured-logging")
+        .key_value_mode_for("critical", KeyValueMode::StructuredMetadata)
+        .key_value_mode_for("fatal", KeyValueMode::Line)
         .build();
 
     // set the actual logger for the facade
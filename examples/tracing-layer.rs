@@ -0,0 +1,30 @@
+fn main() {
+    use fenrir_rs::tracing::FenrirLayer;
+    use fenrir_rs::{Fenrir, KeyValueMode, NetworkingBackend, SerializationFormat};
+    use tracing::{debug, error, info, info_span, trace, warn};
+    use tracing_subscriber::layer::SubscriberExt;
+    use url::Url;
+
+    let my_loki = Fenrir::builder()
+        .endpoint(Url::parse("http://localhost:3100").unwrap())
+        .network(NetworkingBackend::Ureq)
+        .format(SerializationFormat::Json)
+        .include_level()
+        .tag("service", "tracing-layer")
+        .key_value_mode(KeyValueMode::StructuredMetadata)
+        .build();
+
+    // set the actual subscriber for the tracing facade
+    let subscriber = tracing_subscriber::registry().with(FenrirLayer::new(my_loki));
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    // use the regular tracing macros for actual logging in the app, the fields of the spans are
+    // attached to all events which are recorded inside them
+    let span = info_span!("request", request_id = 42);
+    let _entered = span.enter();
+    trace!("This is a TRACE message");
+    debug!("This is a DEBUG message");
+    info!(user = "fenrir", "This is a INFO message");
+    warn!("This is a WARN message");
+    error!("This is a ERROR message");
+}
@@ -0,0 +1,503 @@
+//! A module which contains the buffer for collecting the logging messages before they are sent
+//! to Loki.
+use crate::{Entry, OverflowPolicy, Stream};
+use log::Level;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+
+/// A logging message with its tenant and labels (returned if it could not be stored in the buffer)
+pub(crate) type Record = (Option<String>, BTreeMap<String, String>, Entry);
+
+/// The limits of a [`LogBuffer`] and the policy which is applied if they are reached.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BufferLimits {
+    /// The maximum number of buffered logging messages
+    pub(crate) max_records: Option<usize>,
+    /// The maximum (estimated) number of bytes used by the buffered logging messages
+    pub(crate) max_bytes: Option<usize>,
+    /// The policy which decides which message is dropped if one of the limits is reached
+    pub(crate) overflow_policy: OverflowPolicy,
+}
+
+impl Default for BufferLimits {
+    fn default() -> Self {
+        BufferLimits {
+            max_records: None,
+            max_bytes: None,
+            overflow_policy: OverflowPolicy::DropNewest,
+        }
+    }
+}
+
+/// The [`LogBuffer`] collects all logging messages until they get flushed. Messages with the same
+/// tenant and set of labels are grouped into a single [`Stream`], since this is what Loki expects
+/// and it avoids repeating the same labels for every message.
+#[derive(Default)]
+pub(crate) struct LogBuffer {
+    /// The streams (one per unique label set) which are sent with the next flush
+    streams: Vec<Stream>,
+    /// Maps the fingerprint of a tenant and label set to the index of the corresponding stream
+    fingerprints: HashMap<u64, usize>,
+    /// The number of logging messages stored in all streams
+    records: usize,
+    /// The estimated number of bytes used by all logging messages stored in the streams
+    bytes: usize,
+    /// The number of buffered logging messages per level (indexed by the numeric level)
+    levels: [usize; 6],
+    /// The limits of the buffer
+    limits: BufferLimits,
+    /// The number of logging messages which were dropped because the buffer was full
+    dropped: u64,
+}
+
+impl LogBuffer {
+    /// Create a new, empty buffer which applies the supplied limits.
+    pub(crate) fn with_limits(limits: BufferLimits) -> Self {
+        LogBuffer {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Add a logging message with the supplied tenant and labels to the buffer.
+    ///
+    /// If the buffer is full, the configured [`OverflowPolicy`] decides which message gets dropped.
+    /// For [`OverflowPolicy::Block`] nothing is dropped, instead the message is returned so the
+    /// caller can try again after the buffer was flushed.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn push(
+        &mut self,
+        tenant: Option<String>,
+        labels: BTreeMap<String, String>,
+        entry: Entry,
+    ) -> Result<(), Record> {
+        let size = record_size(&labels, &entry);
+
+        // a message which does not even fit into the empty buffer can never be stored
+        if self
+            .limits
+            .max_bytes
+            .is_some_and(|max_bytes| size > max_bytes)
+        {
+            self.dropped += 1;
+            return Ok(());
+        }
+
+        // make room for the new message according to the overflow policy
+        while self.is_full(size) {
+            let evicted = match self.limits.overflow_policy {
+                OverflowPolicy::DropNewest => false,
+                OverflowPolicy::Block => return Err((tenant, labels, entry)),
+                OverflowPolicy::DropOldest => self.remove_oldest(None),
+                OverflowPolicy::DropLowestSeverity => match self.lowest_level() {
+                    Some(level) if level > entry.level => self.remove_oldest(Some(level)),
+                    _ => false,
+                },
+            };
+            if !evicted {
+                self.dropped += 1;
+                return Ok(());
+            }
+        }
+
+        self.records += 1;
+        self.bytes += size;
+        self.levels[entry.level as usize] += 1;
+
+        // if there is already a stream with the same tenant and labels, just append the new message
+        let fingerprint = fingerprint(tenant.as_deref(), &labels);
+        if let Some(&index) = self.fingerprints.get(&fingerprint) {
+            let stream = &mut self.streams[index];
+            if stream.tenant == tenant && stream.stream == labels {
+                stream.values.push(entry);
+                return Ok(());
+            }
+        }
+
+        // otherwise we have to start a new stream for the label set
+        self.fingerprints.insert(fingerprint, self.streams.len());
+        self.streams.push(Stream {
+            tenant,
+            stream: labels,
+            values: vec![entry],
+        });
+        Ok(())
+    }
+
+    /// Get the number of buffered logging messages.
+    pub(crate) fn len(&self) -> usize {
+        self.records
+    }
+
+    /// Check if there are no buffered logging messages.
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+
+    /// Get the estimated number of bytes used by the buffered logging messages.
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Get all buffered streams.
+    #[cfg(test)]
+    pub(crate) fn streams(&self) -> &[Stream] {
+        &self.streams
+    }
+
+    /// Get the number of logging messages which were dropped because the buffer was full.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Count a logging message which was dropped without trying to store it in the buffer.
+    pub(crate) fn count_dropped(&mut self) {
+        self.dropped += 1;
+    }
+
+    /// Remove all buffered logging messages.
+    #[cfg(test)]
+    pub(crate) fn clear(&mut self) {
+        self.take();
+    }
+
+    /// Remove all buffered streams from the buffer and return them, so they can be serialized
+    /// without holding the lock of the buffer.
+    pub(crate) fn take(&mut self) -> Vec<Stream> {
+        self.fingerprints.clear();
+        self.records = 0;
+        self.bytes = 0;
+        self.levels = [0; 6];
+        std::mem::take(&mut self.streams)
+    }
+
+    /// Check if a message with the supplied size would exceed one of the limits of the buffer.
+    fn is_full(&self, size: usize) -> bool {
+        self.limits
+            .max_records
+            .is_some_and(|max_records| self.records >= max_records)
+            || self
+                .limits
+                .max_bytes
+                .is_some_and(|max_bytes| self.bytes + size > max_bytes)
+    }
+
+    /// Get the lowest severity of all buffered logging messages.
+    fn lowest_level(&self) -> Option<Level> {
+        [
+            Level::Trace,
+            Level::Debug,
+            Level::Info,
+            Level::Warn,
+            Level::Error,
+        ]
+        .into_iter()
+        .find(|level| self.levels[*level as usize] > 0)
+    }
+
+    /// Remove the oldest buffered logging message (with the supplied level, if any). Returns
+    /// `false` if there was no message which could be removed.
+    fn remove_oldest(&mut self, level: Option<Level>) -> bool {
+        // the messages of a stream are not necessarily ordered (e.g. if the queue was drained by
+        // multiple threads), so all matching messages have to be compared
+        let oldest = self
+            .streams
+            .iter()
+            .enumerate()
+            .flat_map(|(stream_index, stream)| {
+                stream
+                    .values
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, entry)| level.map_or(true, |level| entry.level == level))
+                    .map(move |(entry_index, entry)| (entry.timestamp, stream_index, entry_index))
+            })
+            .min_by_key(|(timestamp, _, _)| *timestamp)
+            .map(|(_, stream_index, entry_index)| (stream_index, entry_index));
+        let (stream_index, entry_index) = match oldest {
+            Some(oldest) => oldest,
+            None => return false,
+        };
+
+        let stream = &mut self.streams[stream_index];
+        let entry = stream.values.remove(entry_index);
+        self.records -= 1;
+        self.bytes -= record_size(&stream.stream, &entry);
+        self.levels[entry.level as usize] -= 1;
+        self.dropped += 1;
+
+        // streams without any messages must not be sent to Loki, so they are removed as well
+        if stream.values.is_empty() {
+            let last_index = self.streams.len() - 1;
+            let removed = self.streams.swap_remove(stream_index);
+            let removed_fingerprint = fingerprint(removed.tenant.as_deref(), &removed.stream);
+            if self.fingerprints.get(&removed_fingerprint) == Some(&stream_index) {
+                self.fingerprints.remove(&removed_fingerprint);
+            }
+            if let Some(moved) = self.streams.get(stream_index) {
+                let moved_fingerprint = fingerprint(moved.tenant.as_deref(), &moved.stream);
+                if self.fingerprints.get(&moved_fingerprint) == Some(&last_index) {
+                    self.fingerprints.insert(moved_fingerprint, stream_index);
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Group the supplied streams by their tenant, since every tenant has to be sent in a separate
+/// request.
+pub(crate) fn tenant_batches(streams: &mut [Stream]) -> Vec<(Option<&str>, &[Stream])> {
+    // sort the streams, so all streams of the same tenant are next to each other
+    streams.sort_by(|first, second| first.tenant.cmp(&second.tenant));
+
+    let mut batches = vec![];
+    let mut remaining: &[Stream] = streams;
+    while let Some(first) = remaining.first() {
+        let length = remaining
+            .iter()
+            .take_while(|stream| stream.tenant == first.tenant)
+            .count();
+        let (batch, rest) = remaining.split_at(length);
+        batches.push((first.tenant.as_deref(), batch));
+        remaining = rest;
+    }
+    batches
+}
+
+/// Estimate the number of bytes required for storing a logging message with the supplied labels.
+pub(crate) fn record_size(labels: &BTreeMap<String, String>, entry: &Entry) -> usize {
+    let pairs = labels.iter().chain(entry.metadata.iter());
+    std::mem::size_of::<Entry>()
+        + entry.line.len()
+        + pairs
+            .map(|(name, value)| name.len() + value.len())
+            .sum::<usize>()
+}
+
+/// Calculate the canonical fingerprint of a tenant and label set. Since the labels are stored in a
+/// sorted map, the same labels always result in the same fingerprint, regardless of their insertion
+/// order.
+pub(crate) fn fingerprint(tenant: Option<&str>, labels: &BTreeMap<String, String>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    tenant.hash(&mut hasher);
+    labels.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::{tenant_batches, BufferLimits, LogBuffer};
+    use crate::{Entry, OverflowPolicy};
+    use log::Level;
+    use std::collections::BTreeMap;
+
+    fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn value(line: &str) -> Entry {
+        leveled_value(line, 0, Level::Info)
+    }
+
+    fn leveled_value(line: &str, timestamp: u128, level: Level) -> Entry {
+        Entry {
+            timestamp,
+            line: line.to_string(),
+            metadata: BTreeMap::new(),
+            level,
+        }
+    }
+
+    fn limited_buffer(max_records: usize, overflow_policy: OverflowPolicy) -> LogBuffer {
+        LogBuffer::with_limits(BufferLimits {
+            max_records: Some(max_records),
+            max_bytes: None,
+            overflow_policy,
+        })
+    }
+
+    fn lines(buffer: &LogBuffer) -> Vec<String> {
+        let mut entries: Vec<&Entry> = buffer
+            .streams()
+            .iter()
+            .flat_map(|stream| stream.values.iter())
+            .collect();
+        entries.sort_by_key(|entry| entry.timestamp);
+        entries.iter().map(|entry| entry.line.clone()).collect()
+    }
+
+    #[test]
+    fn messages_with_the_same_labels_are_grouped_into_one_stream() {
+        let mut buffer = LogBuffer::default();
+        buffer
+            .push(None, labels(&[("a", "1"), ("b", "2")]), value("first"))
+            .unwrap();
+        buffer
+            .push(None, labels(&[("b", "2"), ("a", "1")]), value("second"))
+            .unwrap();
+
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.streams().len(), 1);
+        assert_eq!(
+            buffer.streams()[0].values,
+            vec![value("first"), value("second")]
+        );
+    }
+
+    #[test]
+    fn messages_with_different_labels_are_kept_in_separate_streams() {
+        let mut buffer = LogBuffer::default();
+        buffer
+            .push(None, labels(&[("level", "INFO")]), value("first"))
+            .unwrap();
+        buffer
+            .push(None, labels(&[("level", "WARN")]), value("second"))
+            .unwrap();
+        buffer
+            .push(None, labels(&[("level", "INFO")]), value("third"))
+            .unwrap();
+
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.streams().len(), 2);
+        assert_eq!(
+            buffer.streams()[0].values,
+            vec![value("first"), value("third")]
+        );
+
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert!(buffer.streams().is_empty());
+    }
+
+    #[test]
+    fn the_overflow_policy_decides_which_message_is_dropped_if_the_buffer_is_full() {
+        let mut newest = limited_buffer(2, OverflowPolicy::DropNewest);
+        let mut oldest = limited_buffer(2, OverflowPolicy::DropOldest);
+        for buffer in [&mut newest, &mut oldest] {
+            for (timestamp, line) in ["first", "second", "third"].iter().enumerate() {
+                let labels = labels(&[("line", line)]);
+                buffer
+                    .push(
+                        None,
+                        labels,
+                        leveled_value(line, timestamp as u128, Level::Info),
+                    )
+                    .unwrap();
+            }
+            assert_eq!(buffer.len(), 2);
+            assert_eq!(buffer.dropped(), 1);
+        }
+        assert_eq!(lines(&newest), vec!["first", "second"]);
+        assert_eq!(lines(&oldest), vec!["second", "third"]);
+        assert_eq!(oldest.streams().len(), 2);
+    }
+
+    #[test]
+    fn the_oldest_message_is_dropped_even_if_a_stream_is_not_ordered() {
+        let mut buffer = limited_buffer(3, OverflowPolicy::DropOldest);
+        let same = labels(&[("level", "INFO")]);
+        for (line, timestamp) in [("second", 2), ("first", 1), ("third", 3), ("fourth", 4)] {
+            buffer
+                .push(
+                    None,
+                    same.clone(),
+                    leveled_value(line, timestamp, Level::Info),
+                )
+                .unwrap();
+        }
+
+        assert_eq!(lines(&buffer), vec!["second", "third", "fourth"]);
+        assert_eq!(buffer.dropped(), 1);
+    }
+
+    #[test]
+    fn messages_with_the_lowest_severity_are_dropped_first() {
+        let mut buffer = limited_buffer(2, OverflowPolicy::DropLowestSeverity);
+        let info = labels(&[("level", "INFO")]);
+        buffer
+            .push(None, info.clone(), leveled_value("debug", 0, Level::Debug))
+            .unwrap();
+        buffer
+            .push(None, info.clone(), leveled_value("info", 1, Level::Info))
+            .unwrap();
+        buffer
+            .push(None, info.clone(), leveled_value("error", 2, Level::Error))
+            .unwrap();
+        buffer
+            .push(None, info, leveled_value("trace", 3, Level::Trace))
+            .unwrap();
+
+        assert_eq!(lines(&buffer), vec!["info", "error"]);
+        assert_eq!(buffer.dropped(), 2);
+    }
+
+    #[test]
+    fn messages_are_rejected_if_the_buffer_is_full_and_should_block() {
+        let mut buffer = limited_buffer(1, OverflowPolicy::Block);
+        buffer.push(None, labels(&[]), value("first")).unwrap();
+        let (_, _, rejected) = buffer.push(None, labels(&[]), value("second")).unwrap_err();
+
+        assert_eq!(rejected, value("second"));
+        assert_eq!(buffer.dropped(), 0);
+        buffer.clear();
+        buffer.push(None, labels(&[]), value("second")).unwrap();
+    }
+
+    #[test]
+    fn the_size_of_the_buffered_messages_is_limited() {
+        let mut buffer = LogBuffer::with_limits(BufferLimits {
+            max_records: None,
+            max_bytes: Some(std::mem::size_of::<Entry>() * 2 + 10),
+            overflow_policy: OverflowPolicy::DropOldest,
+        });
+        buffer.push(None, labels(&[]), value("first")).unwrap();
+        buffer.push(None, labels(&[]), value("second")).unwrap();
+        buffer
+            .push(None, labels(&[]), value("x".repeat(1024).as_str()))
+            .unwrap();
+
+        assert_eq!(lines(&buffer), vec!["second"]);
+        assert_eq!(buffer.dropped(), 2);
+    }
+
+    #[test]
+    fn streams_are_grouped_into_one_batch_per_tenant() {
+        let mut buffer = LogBuffer::default();
+        let tenant = |name: &str| Some(name.to_string());
+        buffer
+            .push(tenant("b"), labels(&[("a", "1")]), value("first"))
+            .unwrap();
+        buffer
+            .push(None, labels(&[("a", "1")]), value("second"))
+            .unwrap();
+        buffer
+            .push(tenant("a"), labels(&[("a", "1")]), value("third"))
+            .unwrap();
+        buffer
+            .push(tenant("b"), labels(&[("a", "2")]), value("fourth"))
+            .unwrap();
+        buffer
+            .push(tenant("b"), labels(&[("a", "1")]), value("fifth"))
+            .unwrap();
+
+        let mut streams = buffer.take();
+        let batches: Vec<(Option<&str>, usize)> = tenant_batches(&mut streams)
+            .into_iter()
+            .map(|(tenant, streams)| (tenant, streams.len()))
+            .collect();
+        assert_eq!(batches, vec![(None, 1), (Some("a"), 1), (Some("b"), 2)]);
+
+        // the buffer can be used again after the streams were taken
+        assert!(buffer.is_empty());
+        buffer
+            .push(tenant("a"), labels(&[("a", "1")]), value("sixth"))
+            .unwrap();
+        assert_eq!(buffer.streams().len(), 1);
+    }
+}
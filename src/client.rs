@@ -0,0 +1,138 @@
+//! A module which contains the settings of the HTTP clients used by the network backends
+//! (timeouts, proxy, user agent and TLS) and the functions for creating the clients from them.
+use crate::error::FenrirConfigError;
+use crate::tls::TlsConfig;
+use std::time::Duration;
+
+/// The settings of the HTTP clients used by the network backends.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct ClientConfig {
+    /// The maximum time for establishing the connection to the remote endpoint
+    pub(crate) connect_timeout: Duration,
+    /// The maximum time for receiving data of the response
+    pub(crate) read_timeout: Duration,
+    /// The maximum time for sending data of the request
+    pub(crate) write_timeout: Duration,
+    /// The URL of the proxy all requests are sent through
+    pub(crate) proxy: Option<String>,
+    /// The value of the `User-Agent` header (the default of the HTTP client is used if not set)
+    pub(crate) user_agent: Option<String>,
+    /// The custom CA certificates and the client certificate
+    pub(crate) tls: TlsConfig,
+}
+
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(10),
+            write_timeout: Duration::from_secs(10),
+            proxy: None,
+            user_agent: None,
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Check if the HTTP clients of the enabled network backends can be created with the settings.
+    pub(crate) fn validate(&self) -> Result<(), FenrirConfigError> {
+        if self.connect_timeout.is_zero()
+            || self.read_timeout.is_zero()
+            || self.write_timeout.is_zero()
+        {
+            return Err(FenrirConfigError::InvalidTimeout);
+        }
+        self.tls
+            .validate()
+            .map_err(FenrirConfigError::InvalidTlsConfiguration)?;
+        if let Some(proxy) = &self.proxy {
+            #[cfg(feature = "ureq")]
+            ureq::Proxy::new(proxy).map_err(|error| {
+                FenrirConfigError::InvalidProxy(format!("{}: {}", proxy, error))
+            })?;
+            #[cfg(feature = "reqwest-async")]
+            reqwest::Proxy::all(proxy.as_str()).map_err(|error| {
+                FenrirConfigError::InvalidProxy(format!("{}: {}", proxy, error))
+            })?;
+            #[cfg(not(any(feature = "ureq", feature = "reqwest-async")))]
+            let _ = proxy;
+        }
+        Ok(())
+    }
+
+    /// Create the agent which is used by the `ureq` backend for all requests (and which keeps the
+    /// connections to the remote endpoint open between them).
+    #[cfg(feature = "ureq")]
+    pub(crate) fn ureq_agent(&self) -> Result<ureq::Agent, FenrirConfigError> {
+        let mut builder = ureq::AgentBuilder::new()
+            .timeout_connect(self.connect_timeout)
+            .timeout_read(self.read_timeout)
+            .timeout_write(self.write_timeout);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(ureq::Proxy::new(proxy).map_err(|error| {
+                FenrirConfigError::InvalidProxy(format!("{}: {}", proxy, error))
+            })?);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        let tls = self
+            .tls
+            .ureq_tls()
+            .map_err(FenrirConfigError::InvalidTlsConfiguration)?;
+        Ok(tls.apply(builder).build())
+    }
+
+    /// Create the client which is used by the `reqwest` backend for all requests. Since `reqwest`
+    /// does not support separate read and write timeouts, the sum of all timeouts is used as the
+    /// timeout of the whole request.
+    #[cfg(feature = "reqwest-async")]
+    pub(crate) fn reqwest_client(&self) -> Result<reqwest::Client, FenrirConfigError> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.connect_timeout + self.read_timeout + self.write_timeout);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.as_str()).map_err(|error| {
+                FenrirConfigError::InvalidProxy(format!("{}: {}", proxy, error))
+            })?);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent.as_str());
+        }
+        self.tls
+            .apply_reqwest(builder)
+            .and_then(|builder| builder.build().map_err(|error| error.to_string()))
+            .map_err(FenrirConfigError::InvalidTlsConfiguration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClientConfig;
+    use crate::error::FenrirConfigError;
+    use std::time::Duration;
+
+    #[test]
+    fn timeouts_have_to_be_greater_than_zero() {
+        assert_eq!(ClientConfig::default().validate(), Ok(()));
+        let config = ClientConfig {
+            read_timeout: Duration::ZERO,
+            ..ClientConfig::default()
+        };
+        assert_eq!(config.validate(), Err(FenrirConfigError::InvalidTimeout));
+    }
+
+    #[test]
+    #[cfg(any(feature = "ureq", feature = "reqwest-async"))]
+    fn invalid_proxies_are_rejected() {
+        let config = ClientConfig {
+            proxy: Some("ftp://proxy.example.com:21".to_string()),
+            ..ClientConfig::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(FenrirConfigError::InvalidProxy(_))
+        ));
+    }
+}
@@ -0,0 +1,125 @@
+//! A module which contains the compression of the serialized logging messages, which is applied
+//! before sending them to Loki.
+use crate::FenrirError;
+
+/// The [`Compression`] is used to configure how the serialized logging messages are compressed
+/// before sending them to the Loki endpoint (announced by the `Content-Encoding` header).
+///
+/// Loki only accepts compressed bodies for the JSON format, the protobuf format is always
+/// compressed with snappy.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    /// Send the serialized logging messages without compressing them
+    None,
+
+    /// Compress the serialized logging messages with gzip
+    #[cfg(feature = "gzip")]
+    Gzip,
+
+    /// Compress the serialized logging messages with DEFLATE (without the zlib header, as
+    /// expected by Loki)
+    #[cfg(feature = "gzip")]
+    Deflate,
+}
+
+impl Compression {
+    /// Get the value of the `Content-Encoding` header which has to be used when sending logging
+    /// messages compressed with this method (or `None` if they are not compressed).
+    pub(crate) fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Some("gzip"),
+
+            #[cfg(feature = "gzip")]
+            Compression::Deflate => Some("deflate"),
+        }
+    }
+
+    /// Get the static `Content-Encoding` of the method which uses the supplied `Content-Encoding`
+    /// (if any).
+    pub(crate) fn static_content_encoding(value: &str) -> Option<&'static str> {
+        [
+            #[cfg(feature = "gzip")]
+            Compression::Gzip,
+            #[cfg(feature = "gzip")]
+            Compression::Deflate,
+        ]
+        .iter()
+        .filter_map(Compression::content_encoding)
+        .find(|content_encoding| *content_encoding == value)
+    }
+
+    /// Compress the supplied serialized logging messages.
+    pub(crate) fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>, FenrirError> {
+        match self {
+            Compression::None => Ok(data),
+
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                use flate2::write::GzEncoder;
+                use std::io::Write;
+
+                let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+                encoder
+                    .write_all(&data)
+                    .and_then(|_| encoder.finish())
+                    .map_err(|error| FenrirError::Serialization(Box::new(error)))
+            }
+
+            #[cfg(feature = "gzip")]
+            Compression::Deflate => {
+                use flate2::write::DeflateEncoder;
+                use std::io::Write;
+
+                let mut encoder = DeflateEncoder::new(vec![], flate2::Compression::default());
+                encoder
+                    .write_all(&data)
+                    .and_then(|_| encoder.finish())
+                    .map_err(|error| FenrirError::Serialization(Box::new(error)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compression::Compression;
+
+    #[test]
+    fn no_compression_keeps_the_data() {
+        assert_eq!(Compression::None.content_encoding(), None);
+        assert_eq!(
+            Compression::None.compress(b"data".to_vec()).unwrap(),
+            b"data"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn compressed_data_can_be_decompressed_again() {
+        use flate2::read::{DeflateDecoder, GzDecoder};
+        use std::io::Read;
+
+        let data = "the same line again and again\n".repeat(100).into_bytes();
+
+        let gzip = Compression::Gzip.compress(data.clone()).unwrap();
+        assert!(gzip.len() < data.len() / 10);
+        let mut decompressed = vec![];
+        GzDecoder::new(&gzip[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+
+        let deflate = Compression::Deflate.compress(data.clone()).unwrap();
+        let mut decompressed = vec![];
+        DeflateDecoder::new(&deflate[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+
+        assert_eq!(Compression::static_content_encoding("gzip"), Some("gzip"));
+        assert_eq!(Compression::static_content_encoding("br"), None);
+    }
+}
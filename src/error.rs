@@ -0,0 +1,254 @@
+//! A module which contains the errors which can occur when using the crate.
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// The [`FenrirConfigError`] is returned by [`crate::FenrirBuilder::try_build`] if the supplied
+/// configuration is not valid.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FenrirConfigError {
+    /// Neither a [`crate::NetworkingBackend`] nor a custom backend was selected
+    MissingBackend,
+    /// No [`crate::SerializationFormat`] was selected
+    MissingSerializer,
+    /// An async backend was selected, but no handle to a tokio runtime was set
+    MissingRuntime,
+    /// The configured endpoint cannot be used for sending logs to Loki. The reason is attached.
+    InvalidEndpoint(String),
+    /// The serialized messages of the selected format cannot be compressed
+    InvalidCompression,
+    /// The flush threshold has to be greater than 0
+    InvalidFlushThreshold,
+    /// The flush interval has to be greater than 0
+    InvalidFlushInterval,
+    /// The attached tag name is not a valid Loki label name (`[a-zA-Z_][a-zA-Z0-9_]*`)
+    InvalidTagName(String),
+    /// The maximum number of buffered messages (or bytes) has to be greater than 0
+    InvalidBufferLimit,
+    /// The attached filter directive cannot be parsed
+    InvalidFilter(String),
+    /// The attached tenant ID is not a valid Loki tenant ID
+    InvalidTenant(String),
+    /// The additional header with the attached name cannot be sent (invalid name or value)
+    InvalidHeader(String),
+    /// The configured certificates cannot be used by the network backend. The reason is attached.
+    InvalidTlsConfiguration(String),
+    /// The connect, read and write timeouts have to be greater than 0
+    InvalidTimeout,
+    /// The configured proxy cannot be used. The reason is attached.
+    InvalidProxy(String),
+    /// The retry policy has to allow at least one attempt
+    InvalidRetryPolicy,
+    /// The spool directory cannot be created or opened. The reason is attached.
+    InvalidSpoolDirectory(String),
+    /// A spool was configured for an async backend, which does not report failed requests
+    UnsupportedSpool,
+}
+
+impl Display for FenrirConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FenrirConfigError::MissingBackend => write!(
+                f,
+                "You have to select a `NetworkingBackend` before creating an instance of `Fenrir`"
+            ),
+            FenrirConfigError::MissingSerializer => write!(
+                f,
+                "You have to select a `SerializationFormat` before creating an instance of `Fenrir`"
+            ),
+            FenrirConfigError::MissingRuntime => write!(
+                f,
+                "You have to set a runtime handle before creating an instance of `Fenrir` if you want to use an async network backend"
+            ),
+            FenrirConfigError::InvalidEndpoint(reason) => {
+                write!(f, "The endpoint is not valid: {}", reason)
+            }
+            FenrirConfigError::InvalidCompression => write!(
+                f,
+                "The selected `SerializationFormat` is already compressed, use `Compression::None`"
+            ),
+            FenrirConfigError::InvalidFlushThreshold => {
+                write!(f, "You have to set a buffer size greater than 0")
+            }
+            FenrirConfigError::InvalidFlushInterval => {
+                write!(f, "You have to set a flush interval greater than 0")
+            }
+            FenrirConfigError::InvalidTagName(name) => write!(
+                f,
+                "The tag name `{}` is not a valid label name (it has to match `[a-zA-Z_][a-zA-Z0-9_]*`)",
+                name
+            ),
+            FenrirConfigError::InvalidBufferLimit => {
+                write!(f, "You have to set a buffer limit greater than 0")
+            }
+            FenrirConfigError::InvalidFilter(directive) => {
+                write!(f, "The filter directive `{}` is not valid", directive)
+            }
+            FenrirConfigError::InvalidTenant(tenant) => write!(
+                f,
+                "The tenant `{}` is not a valid tenant ID (it has to match `[a-zA-Z0-9!-_.*'()]{{1,150}}`)",
+                tenant
+            ),
+            FenrirConfigError::InvalidHeader(name) => write!(
+                f,
+                "The header `{}` cannot be sent, either its name or its value is not valid",
+                name
+            ),
+            FenrirConfigError::InvalidRetryPolicy => {
+                write!(f, "The retry policy has to allow at least one attempt")
+            }
+            FenrirConfigError::InvalidTlsConfiguration(reason) => {
+                write!(f, "The TLS configuration is not valid: {}", reason)
+            }
+            FenrirConfigError::InvalidTimeout => {
+                write!(f, "You have to set timeouts greater than 0")
+            }
+            FenrirConfigError::InvalidProxy(reason) => {
+                write!(f, "The proxy is not valid: {}", reason)
+            }
+            FenrirConfigError::InvalidSpoolDirectory(reason) => {
+                write!(f, "The spool directory cannot be used: {}", reason)
+            }
+            FenrirConfigError::UnsupportedSpool => write!(
+                f,
+                "The spool cannot be used with an async network backend, since its failed requests are not reported"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FenrirConfigError {}
+
+/// The [`FenrirError`] describes everything which can go wrong while serializing the logging
+/// messages and sending them to Loki.
+#[derive(Debug)]
+pub enum FenrirError {
+    /// The logging messages could not be serialized into the configured format
+    Serialization(Box<dyn std::error::Error + Send + Sync>),
+    /// The URL of the push endpoint could not be created from the configured endpoint
+    InvalidUrl(url::ParseError),
+    /// The request did not finish in time
+    Timeout(Box<dyn std::error::Error + Send + Sync>),
+    /// The request could not be sent to the remote endpoint (e.g. because the connection was
+    /// refused or the TLS handshake failed)
+    Transport(Box<dyn std::error::Error + Send + Sync>),
+    /// The remote endpoint rejected the request with the attached HTTP status code and response
+    /// body (e.g. `400` for entries which are out of order or `429` if a rate limit was hit)
+    Http {
+        /// The HTTP status code returned by the remote endpoint
+        status: u16,
+        /// The body of the response (usually the reason why the request was rejected)
+        body: String,
+        /// The delay the remote endpoint asked to wait before retrying (`Retry-After` header)
+        retry_after: Option<Duration>,
+    },
+    /// Any other error, mainly used by custom backends
+    Other(String),
+}
+
+impl FenrirError {
+    /// Get the HTTP status code if the request was rejected by the remote endpoint.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            FenrirError::Http { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Check if the request was rejected with a `4xx` status code.
+    pub fn is_client_error(&self) -> bool {
+        matches!(self.status(), Some(400..=499))
+    }
+
+    /// Check if the request was rejected with a `5xx` status code.
+    pub fn is_server_error(&self) -> bool {
+        matches!(self.status(), Some(500..=599))
+    }
+
+    /// Check if the request did not finish in time.
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, FenrirError::Timeout(_))
+    }
+}
+
+impl Display for FenrirError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FenrirError::Serialization(error) => {
+                write!(f, "Could not serialize the logging messages: {}", error)
+            }
+            FenrirError::InvalidUrl(error) => {
+                write!(
+                    f,
+                    "Could not create the URL of the push endpoint: {}",
+                    error
+                )
+            }
+            FenrirError::Timeout(error) => write!(f, "The request timed out: {}", error),
+            FenrirError::Transport(error) => write!(f, "Could not send the request: {}", error),
+            FenrirError::Http { status, body, .. } => {
+                write!(
+                    f,
+                    "The request was rejected with status {}: {}",
+                    status, body
+                )
+            }
+            FenrirError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for FenrirError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FenrirError::Serialization(error)
+            | FenrirError::Timeout(error)
+            | FenrirError::Transport(error) => Some(error.as_ref()),
+            FenrirError::InvalidUrl(error) => Some(error),
+            FenrirError::Http { .. } | FenrirError::Other(_) => None,
+        }
+    }
+}
+
+impl From<url::ParseError> for FenrirError {
+    fn from(error: url::ParseError) -> Self {
+        FenrirError::InvalidUrl(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::FenrirError;
+
+    #[test]
+    fn http_errors_can_be_classified_by_their_status_code() {
+        let rejected = FenrirError::Http {
+            status: 400,
+            body: "entry out of order".to_string(),
+            retry_after: None,
+        };
+        assert_eq!(rejected.status(), Some(400));
+        assert!(rejected.is_client_error());
+        assert!(!rejected.is_server_error());
+        assert_eq!(
+            rejected.to_string(),
+            "The request was rejected with status 400: entry out of order"
+        );
+
+        let unavailable = FenrirError::Http {
+            status: 503,
+            body: String::new(),
+            retry_after: None,
+        };
+        assert!(unavailable.is_server_error());
+        assert!(!unavailable.is_client_error());
+    }
+
+    #[test]
+    fn errors_without_a_response_do_not_have_a_status_code() {
+        let error = FenrirError::Timeout("timed out".into());
+        assert!(error.is_timeout());
+        assert_eq!(error.status(), None);
+        assert!(!error.is_client_error());
+        assert!(!error.is_server_error());
+    }
+}
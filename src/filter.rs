@@ -0,0 +1,152 @@
+//! A module which contains the filter deciding which logging messages should be sent to Loki,
+//! based on their level and target.
+use crate::is_target_or_child;
+use log::{LevelFilter, Metadata};
+use std::str::FromStr;
+
+/// The [`LevelFilter`] for all logging messages of a specific target (and its sub-modules).
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Directive {
+    /// The target the directive applies to (including its sub-modules)
+    target: String,
+    /// The most verbose level which is still sent for the matching targets
+    level: LevelFilter,
+}
+
+/// The [`Filter`] decides which logging messages are sent to Loki. It uses a global maximum level
+/// and optional per-target overrides, where the override with the longest matching target wins.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Filter {
+    /// The level used for all targets without a matching directive
+    level: LevelFilter,
+    /// The per-target overrides, sorted by the length of their target (longest first)
+    directives: Vec<Directive>,
+}
+
+impl Filter {
+    /// Create a new filter which sends all messages up to the supplied level.
+    pub(crate) fn new(level: LevelFilter) -> Filter {
+        Filter {
+            level,
+            directives: vec![],
+        }
+    }
+
+    /// Apply directives in the `RUST_LOG` syntax (e.g. `info,my_crate::db=debug,hyper=off`). A
+    /// single level sets the global level, a target without a level enables all of its messages.
+    /// If the directives cannot be parsed, the invalid directive is returned as the error.
+    pub(crate) fn parse(&mut self, directives: &str) -> Result<(), String> {
+        for directive in directives.split(',').map(str::trim) {
+            if directive.is_empty() {
+                continue;
+            }
+            let mut parts = directive.splitn(2, '=');
+            let (target, level) = match (parts.next(), parts.next()) {
+                (Some(level), None) => match LevelFilter::from_str(level) {
+                    Ok(level) => {
+                        self.level = level;
+                        continue;
+                    }
+                    Err(_) => (level, LevelFilter::Trace),
+                },
+                (Some(target), Some(level)) => match LevelFilter::from_str(level.trim()) {
+                    Ok(level) => (target.trim(), level),
+                    Err(_) => return Err(directive.to_string()),
+                },
+                _ => return Err(directive.to_string()),
+            };
+            if target.is_empty() || target.contains(char::is_whitespace) {
+                return Err(directive.to_string());
+            }
+
+            // later directives for the same target replace the earlier ones
+            self.directives.retain(|existing| existing.target != target);
+            self.directives.push(Directive {
+                target: target.to_string(),
+                level,
+            });
+        }
+        self.directives
+            .sort_by_key(|directive| std::cmp::Reverse(directive.target.len()));
+        Ok(())
+    }
+
+    /// Check if the message with the supplied metadata should be sent.
+    pub(crate) fn enabled(&self, metadata: &Metadata) -> bool {
+        let level = self
+            .directives
+            .iter()
+            .find(|directive| is_target_or_child(metadata.target(), &directive.target))
+            .map_or(self.level, |directive| directive.level);
+        metadata.level() <= level
+    }
+
+    /// Get the most verbose level of all messages which could pass the filter.
+    pub(crate) fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|directive| directive.level)
+            .fold(self.level, Ord::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::Filter;
+    use log::{Level, LevelFilter, Metadata};
+
+    fn enabled(filter: &Filter, target: &str, level: Level) -> bool {
+        filter.enabled(&Metadata::builder().target(target).level(level).build())
+    }
+
+    #[test]
+    fn directives_override_the_global_level_for_their_targets() {
+        let mut filter = Filter::new(LevelFilter::Trace);
+        filter
+            .parse("info, my_crate::db=debug,hyper=off,my_crate=warn")
+            .unwrap();
+
+        assert!(enabled(&filter, "app", Level::Info));
+        assert!(!enabled(&filter, "app", Level::Debug));
+        assert!(enabled(&filter, "my_crate::db::pool", Level::Debug));
+        assert!(!enabled(&filter, "my_crate::db::pool", Level::Trace));
+        assert!(!enabled(&filter, "my_crate::api", Level::Info));
+        assert!(enabled(&filter, "my_crate::api", Level::Warn));
+        assert!(!enabled(&filter, "hyper::client", Level::Error));
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn directives_do_not_apply_to_crates_sharing_their_prefix() {
+        let mut filter = Filter::new(LevelFilter::Info);
+        filter.parse("hyper=off").unwrap();
+
+        assert!(!enabled(&filter, "hyper", Level::Error));
+        assert!(!enabled(&filter, "hyper::proto::h1", Level::Error));
+        assert!(enabled(&filter, "hyper_util::client", Level::Info));
+        assert!(enabled(&filter, "hyperlocal", Level::Info));
+    }
+
+    #[test]
+    fn a_target_without_a_level_enables_all_of_its_messages() {
+        let mut filter = Filter::new(LevelFilter::Error);
+        filter.parse("my_crate").unwrap();
+
+        assert!(enabled(&filter, "my_crate", Level::Trace));
+        assert!(!enabled(&filter, "other_crate", Level::Warn));
+    }
+
+    #[test]
+    fn invalid_directives_are_rejected() {
+        let mut filter = Filter::new(LevelFilter::Trace);
+        assert_eq!(
+            filter.parse("info,my_crate=loud"),
+            Err("my_crate=loud".to_string())
+        );
+        assert_eq!(filter.parse("=debug"), Err("=debug".to_string()));
+        assert_eq!(
+            filter.parse("my crate=debug"),
+            Err("my crate=debug".to_string())
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! A module which contains the formats the logging lines sent to Loki can be rendered in.
+use log::Record;
+use std::sync::Arc;
+
+/// The function which renders the line of a logging message from the record and the key-value-pairs
+/// which should be stored in the line (see [`LineFormat::custom`]).
+pub type LineFormatter = Arc<dyn Fn(&Record, &[(String, String)]) -> String + Send + Sync>;
+
+/// The [`LineFormat`] is used to configure how the line of a logging message, which is sent to
+/// Loki, is rendered. The formats [`LineFormat::Logfmt`] and [`LineFormat::JsonLine`] include the
+/// metadata of the message (level, target, module, file, line and thread name), so they can be
+/// extracted in Grafana using the `| logfmt` and `| json` parsers.
+#[derive(Clone, Default)]
+pub enum LineFormat {
+    /// Send just the logging message itself, the key-value-pairs stored in the line (see
+    /// `KeyValueMode::Line`) are appended in the `key=value` format
+    #[default]
+    Plain,
+
+    /// Render the message and its metadata in the logfmt format, e.g.
+    /// `level=info msg="Hello Loki" target=app module=app file=src/main.rs line=42`
+    Logfmt,
+
+    /// Render the message and its metadata as a JSON object, e.g.
+    /// `{"file":"src/main.rs","level":"info","line":42,"module":"app","msg":"Hello Loki"}`
+    #[cfg(feature = "json")]
+    JsonLine,
+
+    /// Render the line using the supplied function. The function receives the whole record and the
+    /// key-value-pairs which should be stored in the line (e.g. the ones moved out of the labels),
+    /// it is responsible for including them in the line
+    Custom(LineFormatter),
+}
+
+impl LineFormat {
+    /// Create a [`LineFormat::Custom`] format which renders the lines using the supplied function.
+    /// Besides the record, the function receives the key-value-pairs which should be stored in the
+    /// line (in the order they were logged).
+    ///
+    /// # Example
+    /// ```
+    /// use fenrir_rs::LineFormat;
+    ///
+    /// let format = LineFormat::custom(|record, fields| {
+    ///     let mut line = format!("[{}] {}", record.target(), record.args());
+    ///     for (key, value) in fields {
+    ///         line.push_str(&format!(" {}={}", key, value));
+    ///     }
+    ///     line
+    /// });
+    /// ```
+    pub fn custom<F>(formatter: F) -> LineFormat
+    where
+        F: Fn(&Record, &[(String, String)]) -> String + Send + Sync + 'static,
+    {
+        LineFormat::Custom(Arc::new(formatter))
+    }
+
+    /// Render the line of the supplied record, including the key-value-pairs which should be
+    /// stored in the line (in the supplied order).
+    pub(crate) fn render(&self, record: &Record, fields: &[(String, String)]) -> String {
+        match self {
+            LineFormat::Plain => {
+                let mut line = record.args().to_string();
+                for (key, value) in fields {
+                    line.push(' ');
+                    line.push_str(&logfmt_pair(key, value));
+                }
+                line
+            }
+
+            LineFormat::Logfmt => metadata(record)
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .chain(fields.iter().cloned())
+                .map(|(key, value)| logfmt_pair(&key, &value))
+                .collect::<Vec<_>>()
+                .join(" "),
+
+            #[cfg(feature = "json")]
+            LineFormat::JsonLine => {
+                let mut object = serde_json::Map::new();
+                for (key, value) in fields {
+                    object.insert(key.clone(), serde_json::Value::from(value.as_str()));
+                }
+                // the metadata of the message must not be overwritten by the key-value-pairs
+                for (key, value) in metadata(record) {
+                    let value = match (key, record.line()) {
+                        ("line", Some(line)) => serde_json::Value::from(line),
+                        _ => serde_json::Value::from(value),
+                    };
+                    object.insert(key.to_string(), value);
+                }
+                serde_json::Value::Object(object).to_string()
+            }
+
+            LineFormat::Custom(formatter) => formatter(record, fields),
+        }
+    }
+}
+
+impl std::fmt::Debug for LineFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineFormat::Plain => f.write_str("Plain"),
+            LineFormat::Logfmt => f.write_str("Logfmt"),
+            #[cfg(feature = "json")]
+            LineFormat::JsonLine => f.write_str("JsonLine"),
+            LineFormat::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// Get the message and the available metadata of the supplied record.
+fn metadata(record: &Record) -> Vec<(&'static str, String)> {
+    let mut metadata = vec![
+        ("level", record.level().as_str().to_lowercase()),
+        ("msg", record.args().to_string()),
+        ("target", record.target().to_string()),
+    ];
+    if let Some(module) = record.module_path() {
+        metadata.push(("module", module.to_string()));
+    }
+    if let Some(file) = record.file() {
+        metadata.push(("file", file.to_string()));
+    }
+    if let Some(line) = record.line() {
+        metadata.push(("line", line.to_string()));
+    }
+    if let Some(thread) = std::thread::current().name() {
+        metadata.push(("thread", thread.to_string()));
+    }
+    metadata
+}
+
+/// Render a key-value-pair in the logfmt format, the value is quoted if required. Within the
+/// quotes only `"` and `\` are escaped, all other characters (e.g. non-ASCII ones) are kept as
+/// they are instead of using the escapes of Rust's debug output.
+fn logfmt_pair(key: &str, value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '=' | '\\'));
+    if !needs_quotes {
+        return format!("{}={}", key, value);
+    }
+
+    let mut pair = format!("{}=\"", key);
+    for c in value.chars() {
+        if matches!(c, '"' | '\\') {
+            pair.push('\\');
+        }
+        pair.push(c);
+    }
+    pair.push('"');
+    pair
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::LineFormat;
+    use log::{Level, Record};
+
+    fn render(format: &LineFormat, fields: &[(String, String)]) -> String {
+        format.render(
+            &Record::builder()
+                .args(format_args!("Hello Loki"))
+                .level(Level::Warn)
+                .target("app")
+                .module_path_static(Some("app::jobs"))
+                .file_static(Some("src/jobs.rs"))
+                .line(Some(42))
+                .build(),
+            fields,
+        )
+    }
+
+    #[test]
+    fn the_plain_format_appends_the_fields_to_the_message() {
+        assert_eq!(render(&LineFormat::Plain, &[]), "Hello Loki");
+        assert_eq!(
+            render(
+                &LineFormat::Plain,
+                &[("user".to_string(), "jane doe".to_string())]
+            ),
+            "Hello Loki user=\"jane doe\""
+        );
+    }
+
+    #[test]
+    fn the_logfmt_format_contains_the_metadata_and_the_fields() {
+        let line = render(
+            &LineFormat::Logfmt,
+            &[("job".to_string(), "cleanup".to_string())],
+        );
+        assert!(line.starts_with(
+            "level=warn msg=\"Hello Loki\" target=app module=app::jobs file=src/jobs.rs line=42"
+        ));
+        assert!(line.ends_with(" job=cleanup"));
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn the_json_line_format_contains_the_metadata_and_the_fields() {
+        let line = render(
+            &LineFormat::JsonLine,
+            &[
+                ("job".to_string(), "cleanup".to_string()),
+                ("level".to_string(), "overwritten".to_string()),
+            ],
+        );
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["msg"], "Hello Loki");
+        assert_eq!(value["level"], "warn");
+        assert_eq!(value["line"], 42);
+        assert_eq!(value["module"], "app::jobs");
+        assert_eq!(value["job"], "cleanup");
+    }
+
+    #[test]
+    fn a_custom_format_renders_the_whole_record_and_the_fields() {
+        let format = LineFormat::custom(|record, fields| {
+            format!("{}: {} {:?}", record.level(), record.args(), fields)
+        });
+        assert_eq!(
+            render(&format, &[("job".to_string(), "cleanup".to_string())]),
+            "WARN: Hello Loki [(\"job\", \"cleanup\")]"
+        );
+    }
+
+    #[test]
+    fn only_quotes_and_backslashes_are_escaped_in_logfmt_values() {
+        let line = render(
+            &LineFormat::Plain,
+            &[
+                ("path".to_string(), "C:\\temp".to_string()),
+                ("quote".to_string(), "say \"hi\"".to_string()),
+                ("text".to_string(), "first\nsecond über".to_string()),
+                ("empty".to_string(), String::new()),
+            ],
+        );
+        assert_eq!(
+            line,
+            "Hello Loki path=\"C:\\\\temp\" quote=\"say \\\"hi\\\"\" text=\"first\nsecond über\" empty=\"\""
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! A module which contains the guard for detecting logging messages which are created while the
+//! crate itself is sending messages to Loki (e.g. by the used networking libraries).
+use std::cell::Cell;
+#[cfg(feature = "async-tokio")]
+use std::future::Future;
+#[cfg(feature = "async-tokio")]
+use std::pin::Pin;
+#[cfg(feature = "async-tokio")]
+use std::task::{Context, Poll};
+
+thread_local! {
+    /// Set to `true` while the current thread is flushing or sending logging messages
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Check if the current thread is currently flushing or sending logging messages. Messages logged
+/// in this state must be dropped, since sending them would cause an infinite loop.
+pub(crate) fn is_active() -> bool {
+    ACTIVE.with(Cell::get)
+}
+
+/// The [`ReentrancyGuard`] marks the current thread as flushing or sending logging messages until
+/// it gets dropped.
+pub(crate) struct ReentrancyGuard {
+    /// The state of the thread before the guard was created (guards might be nested)
+    previous: bool,
+}
+
+impl ReentrancyGuard {
+    /// Mark the current thread as flushing or sending logging messages.
+    pub(crate) fn enter() -> ReentrancyGuard {
+        ReentrancyGuard {
+            previous: ACTIVE.with(|active| active.replace(true)),
+        }
+    }
+}
+
+impl Drop for ReentrancyGuard {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| active.set(self.previous));
+    }
+}
+
+/// The [`Guarded`] future marks the thread which is polling the wrapped future as sending logging
+/// messages while it is polled, since the future might run on any thread of the runtime.
+#[cfg(feature = "async-tokio")]
+pub(crate) struct Guarded<F>(pub(crate) F);
+
+#[cfg(feature = "async-tokio")]
+impl<F: Future + Unpin> Future for Guarded<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let _guard = ReentrancyGuard::enter();
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::guard::{is_active, ReentrancyGuard};
+
+    #[test]
+    fn the_guard_marks_the_current_thread_until_it_is_dropped() {
+        assert!(!is_active());
+        {
+            let _outer = ReentrancyGuard::enter();
+            {
+                let _inner = ReentrancyGuard::enter();
+                assert!(is_active());
+            }
+            assert!(is_active());
+            assert!(!std::thread::spawn(is_active).join().unwrap());
+        }
+        assert!(!is_active());
+    }
+}
@@ -0,0 +1,223 @@
+//! A module which contains the [`FenrirHandle`], the async interface of [`crate::Fenrir`] for
+//! applications based on the tokio runtime.
+use crate::queue::LogQueue;
+use crate::spool::Spool;
+use crate::worker::FlushHandle;
+use crate::{deliver_streams, shutdown, Compression, FenrirBackend, FenrirError, SerializationFn};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+/// The commands which are sent from the [`FenrirHandle`] to its sender task
+enum Command {
+    /// Send all buffered logging messages and reply with the result
+    Flush(oneshot::Sender<Result<(), FenrirError>>),
+    /// Send all buffered logging messages, wait for the requests which are still running in the
+    /// background (or until the timeout elapsed), reply with the result and stop the task
+    Shutdown(Duration, oneshot::Sender<Result<(), FenrirError>>),
+}
+
+/// Everything the sender task needs for sending the buffered logging messages.
+pub(crate) struct SenderContext {
+    /// The queue which contains the logging messages
+    pub(crate) log_queue: Arc<LogQueue>,
+    /// The function which serializes the logging messages
+    pub(crate) serializer: SerializationFn,
+    /// The content type of the serialized logging messages
+    pub(crate) content_type: &'static str,
+    /// The compression applied to the serialized logging messages
+    pub(crate) compression: Compression,
+    /// The backend which reports the actual result of sending the logging messages
+    pub(crate) delivery_backend: Arc<dyn FenrirBackend>,
+    /// The backend which is used by the background worker (for waiting for its requests)
+    pub(crate) backend: Arc<dyn FenrirBackend>,
+    /// The spool for the logging messages which could not be sent
+    pub(crate) spool: Option<Arc<Spool>>,
+    /// The handle of the background worker which flushes the messages periodically
+    pub(crate) worker: FlushHandle,
+}
+
+impl SenderContext {
+    /// Send all buffered logging messages on a blocking thread of the runtime (since the
+    /// serialization and the spool would block the current task otherwise).
+    async fn flush(self: &Arc<SenderContext>) -> Result<(), FenrirError> {
+        let context = self.clone();
+        tokio::task::spawn_blocking(move || {
+            deliver_streams(
+                &context.log_queue,
+                context.serializer,
+                context.content_type,
+                context.compression,
+                context.delivery_backend.as_ref(),
+                context.spool.as_deref(),
+            )
+        })
+        .await
+        .unwrap_or_else(|error| Err(FenrirError::Other(error.to_string())))
+    }
+
+    /// Send all buffered logging messages and wait until the flush of the background worker and
+    /// its requests are finished as well.
+    async fn shutdown(self: &Arc<SenderContext>, timeout: Duration) -> Result<(), FenrirError> {
+        let deadline = Instant::now() + timeout;
+        let flushed = match tokio::time::timeout(timeout, self.flush()).await {
+            Ok(result) => result,
+            Err(_) => Err(shutdown_timeout()),
+        };
+
+        let context = self.clone();
+        let finished = tokio::task::spawn_blocking(move || {
+            shutdown::flush_and_wait(
+                &context.worker,
+                context.backend.as_ref(),
+                deadline.saturating_duration_since(Instant::now()),
+            )
+        })
+        .await
+        .unwrap_or(false);
+
+        flushed.and(if finished {
+            Ok(())
+        } else {
+            Err(shutdown_timeout())
+        })
+    }
+}
+
+/// The error which is reported if the shutdown did not finish in time.
+fn shutdown_timeout() -> FenrirError {
+    FenrirError::Timeout("The logs were not sent to Loki before the timeout elapsed".into())
+}
+
+/// The [`FenrirHandle`] allows async applications to send the buffered logging messages of a
+/// [`crate::Fenrir`] instance and to wait until they were delivered, e.g. in the graceful shutdown
+/// handler of a web server. It is created by [`crate::Fenrir::handle`] and can be cloned freely.
+///
+/// All requests of the handles of an instance are processed by a single sender task on the tokio
+/// runtime, so the serialization and the requests do not block the calling task.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use fenrir_rs::{Fenrir, NetworkingBackend, SerializationFormat};
+///
+/// # #[cfg(all(feature = "reqwest-async", feature = "json"))]
+/// # #[tokio::main]
+/// # async fn main() {
+/// let fenrir = Fenrir::builder()
+///     .network(NetworkingBackend::Reqwest)
+///     .format(SerializationFormat::Json)
+///     .tokio_rt_handle_current()
+///     .build();
+/// let handle = fenrir.handle();
+/// log::set_boxed_logger(Box::new(fenrir)).unwrap();
+///
+/// // ... run the application until it should exit
+///
+/// if let Err(error) = handle.shutdown(Duration::from_secs(5)).await {
+///     eprintln!("The last logs could not be sent to Loki: {}", error);
+/// }
+/// # }
+/// # #[cfg(not(all(feature = "reqwest-async", feature = "json")))]
+/// # fn main() {}
+/// ```
+#[derive(Clone)]
+pub struct FenrirHandle {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl FenrirHandle {
+    /// Spawn the sender task on the supplied runtime and create a handle for it.
+    pub(crate) fn spawn(
+        context: SenderContext,
+        runtime_handle: &tokio::runtime::Handle,
+    ) -> FenrirHandle {
+        let (commands, mut receiver) = mpsc::unbounded_channel();
+        let context = Arc::new(context);
+        runtime_handle.spawn(async move {
+            while let Some(command) = receiver.recv().await {
+                match command {
+                    Command::Flush(reply) => {
+                        let _ = reply.send(context.flush().await);
+                    }
+                    Command::Shutdown(timeout, reply) => {
+                        let _ = reply.send(context.shutdown(timeout).await);
+                        break;
+                    }
+                }
+            }
+        });
+        FenrirHandle { commands }
+    }
+
+    /// Send all buffered logging messages to Loki and wait until the requests are finished.
+    ///
+    /// In contrast to [`log::Log::flush`], the result of the requests is reported, e.g. a
+    /// [`FenrirError::Http`] if Loki rejected the messages. Messages which are already sent by the
+    /// background worker of the instance are not waited for, use [`FenrirHandle::shutdown`] for
+    /// this.
+    ///
+    /// # Example
+    /// ```
+    /// use fenrir_rs::{Fenrir, NetworkingBackend, SerializationFormat};
+    ///
+    /// # #[cfg(all(feature = "reqwest-async", feature = "json"))]
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let fenrir = Fenrir::builder()
+    ///     .network(NetworkingBackend::Reqwest)
+    ///     .format(SerializationFormat::Json)
+    ///     .tokio_rt_handle_current()
+    ///     .build();
+    /// assert!(fenrir.handle().flush().await.is_ok());
+    /// # }
+    /// # #[cfg(not(all(feature = "reqwest-async", feature = "json")))]
+    /// # fn main() {}
+    /// ```
+    pub async fn flush(&self) -> Result<(), FenrirError> {
+        let (reply, result) = oneshot::channel();
+        self.request(Command::Flush(reply), result).await
+    }
+
+    /// Send all buffered logging messages to Loki and wait until all requests (including the ones
+    /// of the background worker) are finished or the supplied timeout elapsed. Afterwards, the
+    /// sender task is stopped and all handles of the instance return an error.
+    ///
+    /// The messages which are logged afterwards are still sent by the background worker of the
+    /// instance, but cannot be awaited anymore.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use fenrir_rs::{Fenrir, NetworkingBackend, SerializationFormat};
+    ///
+    /// # #[cfg(all(feature = "reqwest-async", feature = "json"))]
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let fenrir = Fenrir::builder()
+    ///     .network(NetworkingBackend::Reqwest)
+    ///     .format(SerializationFormat::Json)
+    ///     .tokio_rt_handle_current()
+    ///     .build();
+    /// assert!(fenrir.handle().shutdown(Duration::from_secs(5)).await.is_ok());
+    /// # }
+    /// # #[cfg(not(all(feature = "reqwest-async", feature = "json")))]
+    /// # fn main() {}
+    /// ```
+    pub async fn shutdown(&self, timeout: Duration) -> Result<(), FenrirError> {
+        let (reply, result) = oneshot::channel();
+        self.request(Command::Shutdown(timeout, reply), result)
+            .await
+    }
+
+    /// Send the command to the sender task and wait for its reply.
+    async fn request(
+        &self,
+        command: Command,
+        result: oneshot::Receiver<Result<(), FenrirError>>,
+    ) -> Result<(), FenrirError> {
+        let stopped = || FenrirError::Other("The sender task was already shut down".to_string());
+        self.commands.send(command).map_err(|_| stopped())?;
+        result.await.unwrap_or_else(|_| Err(stopped()))
+    }
+}
@@ -1,13 +1,13 @@
 //! A module which contains the implementation for the [`FenrirBackend`] trait which ignores all
 //! network requests.
-use crate::{AuthenticationMethod, FenrirBackend};
+use crate::{AuthenticationMethod, FenrirBackend, FenrirError, Payload};
 use std::any::TypeId;
 
 /// The [`NoopBackend`] is used by default and does ignore all logging messages.
 pub(crate) struct NoopBackend;
 
 impl FenrirBackend for NoopBackend {
-    fn send(&self, _: Vec<u8>) -> Result<(), String> {
+    fn send(&self, _: Payload) -> Result<(), FenrirError> {
         Ok(())
     }
 
@@ -0,0 +1,177 @@
+//! A module which contains the serialization of the logging messages into the JSON encoding of
+//! OTLP logs (`ExportLogsServiceRequest`), which is accepted by the OTLP endpoint of Loki and any
+//! other OpenTelemetry receiver.
+use crate::{FenrirError, Streams};
+use log::Level;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The content type which has to be used when sending JSON encoded OTLP logs
+pub(crate) const CONTENT_TYPE: &str = "application/json";
+
+/// The name of the instrumentation scope all log records are attached to
+const SCOPE_NAME: &str = "fenrir-rs";
+
+/// The `ExportLogsServiceRequest` message which contains all logging messages of a request
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportLogsServiceRequest {
+    resource_logs: Vec<ResourceLogs>,
+}
+
+/// All log records which share the same set of labels (the resource attributes)
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceLogs {
+    resource: Resource,
+    scope_logs: Vec<ScopeLogs>,
+}
+
+/// The resource which produced the log records, described by its attributes
+#[derive(Serialize)]
+struct Resource {
+    attributes: Vec<KeyValue>,
+}
+
+/// All log records of a single instrumentation scope
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScopeLogs {
+    scope: InstrumentationScope,
+    log_records: Vec<LogRecord>,
+}
+
+/// The instrumentation scope which created the log records
+#[derive(Serialize)]
+struct InstrumentationScope {
+    name: &'static str,
+    version: &'static str,
+}
+
+/// A single logging message with its severity and attributes
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LogRecord {
+    /// The timestamp in nanoseconds since the UNIX epoch (64 bit integers are encoded as strings)
+    time_unix_nano: String,
+    severity_number: u8,
+    severity_text: &'static str,
+    body: AnyValue,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attributes: Vec<KeyValue>,
+}
+
+/// A single attribute of a resource or a log record
+#[derive(Serialize)]
+struct KeyValue {
+    key: String,
+    value: AnyValue,
+}
+
+/// The value of an attribute or the body of a log record (only strings are used)
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AnyValue {
+    string_value: String,
+}
+
+/// Convert the supplied labels or structured metadata into OTLP attributes.
+fn attributes(values: &BTreeMap<String, String>) -> Vec<KeyValue> {
+    values
+        .iter()
+        .map(|(key, value)| KeyValue {
+            key: key.clone(),
+            value: AnyValue {
+                string_value: value.clone(),
+            },
+        })
+        .collect()
+}
+
+/// Get the OTLP `SeverityNumber` of the supplied level (the first value of the matching range).
+fn severity_number(level: Level) -> u8 {
+    match level {
+        Level::Trace => 1,
+        Level::Debug => 5,
+        Level::Info => 9,
+        Level::Warn => 13,
+        Level::Error => 17,
+    }
+}
+
+/// Serialize the supplied streams into the JSON encoding of an `ExportLogsServiceRequest`.
+pub(crate) fn serialize(data: &Streams) -> Result<Vec<u8>, FenrirError> {
+    let request = ExportLogsServiceRequest {
+        resource_logs: data
+            .streams
+            .iter()
+            .map(|stream| ResourceLogs {
+                resource: Resource {
+                    attributes: attributes(&stream.stream),
+                },
+                scope_logs: vec![ScopeLogs {
+                    scope: InstrumentationScope {
+                        name: SCOPE_NAME,
+                        version: env!("CARGO_PKG_VERSION"),
+                    },
+                    log_records: stream
+                        .values
+                        .iter()
+                        .map(|entry| LogRecord {
+                            time_unix_nano: entry.timestamp.to_string(),
+                            severity_number: severity_number(entry.level),
+                            severity_text: entry.level.as_str(),
+                            body: AnyValue {
+                                string_value: entry.line.clone(),
+                            },
+                            attributes: attributes(&entry.metadata),
+                        })
+                        .collect(),
+                }],
+            })
+            .collect(),
+    };
+    serde_json::to_vec(&request).map_err(|error| FenrirError::Serialization(Box::new(error)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::otlp::serialize;
+    use crate::{Entry, Stream, Streams};
+    use log::Level;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn streams_are_serialized_as_otlp_logs() {
+        let streams = vec![Stream {
+            tenant: None,
+            stream: BTreeMap::from([("service".to_string(), "api".to_string())]),
+            values: vec![Entry {
+                timestamp: 1_700_000_000_000_000_001,
+                line: "request failed".to_string(),
+                metadata: BTreeMap::from([("trace_id".to_string(), "abc".to_string())]),
+                level: Level::Warn,
+            }],
+        }];
+        let serialized = serialize(&Streams { streams: &streams }).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&serialized).unwrap();
+
+        let resource_logs = &value["resourceLogs"][0];
+        assert_eq!(
+            resource_logs["resource"]["attributes"],
+            serde_json::json!([{"key": "service", "value": {"stringValue": "api"}}])
+        );
+        let scope_logs = &resource_logs["scopeLogs"][0];
+        assert_eq!(scope_logs["scope"]["name"], "fenrir-rs");
+        assert_eq!(
+            scope_logs["logRecords"][0],
+            serde_json::json!({
+                "timeUnixNano": "1700000000000000001",
+                "severityNumber": 13,
+                "severityText": "WARN",
+                "body": {"stringValue": "request failed"},
+                "attributes": [{"key": "trace_id", "value": {"stringValue": "abc"}}],
+            })
+        );
+    }
+}
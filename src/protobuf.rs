@@ -0,0 +1,160 @@
+//! A module which contains the serialization of the logging messages into the snappy-compressed
+//! protobuf format (`logproto.PushRequest`) which is natively used by Loki for ingestion.
+use crate::{FenrirError, Streams};
+use prost::Message;
+use std::collections::BTreeMap;
+
+/// The content type which has to be used when sending protobuf encoded messages to Loki
+pub(crate) const CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// The `google.protobuf.Timestamp` message used for the timestamps of the single entries
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct Timestamp {
+    #[prost(int64, tag = "1")]
+    pub(crate) seconds: i64,
+    #[prost(int32, tag = "2")]
+    pub(crate) nanos: i32,
+}
+
+/// A single label of the structured metadata attached to an entry
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct LabelPairAdapter {
+    #[prost(string, tag = "1")]
+    pub(crate) name: String,
+    #[prost(string, tag = "2")]
+    pub(crate) value: String,
+}
+
+/// A single log line with the corresponding timestamp
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct EntryAdapter {
+    #[prost(message, optional, tag = "1")]
+    pub(crate) timestamp: Option<Timestamp>,
+    #[prost(string, tag = "2")]
+    pub(crate) line: String,
+    #[prost(message, repeated, tag = "3")]
+    pub(crate) structured_metadata: Vec<LabelPairAdapter>,
+}
+
+/// All entries which share the same set of labels
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct StreamAdapter {
+    #[prost(string, tag = "1")]
+    pub(crate) labels: String,
+    #[prost(message, repeated, tag = "2")]
+    pub(crate) entries: Vec<EntryAdapter>,
+    #[prost(uint64, tag = "3")]
+    pub(crate) hash: u64,
+}
+
+/// The base message Loki expects on its push endpoint
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct PushRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub(crate) streams: Vec<StreamAdapter>,
+}
+
+/// Format the labels of a stream in the Prometheus label selector syntax (e.g. `{a="b", c="d"}`),
+/// which is the representation Loki expects in the protobuf messages.
+fn format_labels(labels: &BTreeMap<String, String>) -> String {
+    let formatted: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let escaped = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, escaped)
+        })
+        .collect();
+    format!("{{{}}}", formatted.join(", "))
+}
+
+/// Serialize the supplied streams into a snappy-compressed `logproto.PushRequest` message
+pub(crate) fn serialize(data: &Streams) -> Result<Vec<u8>, FenrirError> {
+    let mut request = PushRequest {
+        streams: Vec::with_capacity(data.streams.len()),
+    };
+    for stream in data.streams {
+        let mut entries = Vec::with_capacity(stream.values.len());
+        for entry in &stream.values {
+            entries.push(EntryAdapter {
+                timestamp: Some(Timestamp {
+                    seconds: (entry.timestamp / 1_000_000_000) as i64,
+                    nanos: (entry.timestamp % 1_000_000_000) as i32,
+                }),
+                line: entry.line.clone(),
+                structured_metadata: entry
+                    .metadata
+                    .iter()
+                    .map(|(name, value)| LabelPairAdapter {
+                        name: name.clone(),
+                        value: value.clone(),
+                    })
+                    .collect(),
+            });
+        }
+        request.streams.push(StreamAdapter {
+            labels: format_labels(&stream.stream),
+            entries,
+            hash: 0,
+        });
+    }
+
+    snap::raw::Encoder::new()
+        .compress_vec(&request.encode_to_vec())
+        .map_err(|error| FenrirError::Serialization(Box::new(error)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protobuf::{serialize, LabelPairAdapter, PushRequest, Timestamp};
+    use crate::{Entry, Stream, Streams};
+    use prost::Message;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn serializing_streams_results_in_a_snappy_compressed_push_request() {
+        let streams = vec![Stream {
+            tenant: None,
+            stream: BTreeMap::from([
+                ("service".to_string(), "test".to_string()),
+                ("level".to_string(), "INFO \"quoted\"".to_string()),
+            ]),
+            values: vec![Entry {
+                timestamp: 1688652000123456789,
+                line: "Hello Loki".to_string(),
+                metadata: BTreeMap::from([("request_id".to_string(), "42".to_string())]),
+                level: log::Level::Info,
+            }],
+        }];
+
+        let serialized = serialize(&Streams { streams: &streams }).unwrap();
+        let decompressed = snap::raw::Decoder::new()
+            .decompress_vec(&serialized)
+            .unwrap();
+        let request = PushRequest::decode(decompressed.as_slice()).unwrap();
+
+        assert_eq!(request.streams.len(), 1);
+        assert_eq!(
+            request.streams[0].labels,
+            "{level=\"INFO \\\"quoted\\\"\", service=\"test\"}"
+        );
+        assert_eq!(request.streams[0].entries.len(), 1);
+        assert_eq!(request.streams[0].entries[0].line, "Hello Loki");
+        assert_eq!(
+            request.streams[0].entries[0].timestamp,
+            Some(Timestamp {
+                seconds: 1688652000,
+                nanos: 123456789,
+            })
+        );
+        assert_eq!(
+            request.streams[0].entries[0].structured_metadata,
+            vec![LabelPairAdapter {
+                name: "request_id".to_string(),
+                value: "42".to_string(),
+            }]
+        );
+    }
+}
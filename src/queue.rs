@@ -0,0 +1,317 @@
+//! A module which contains the queue the logging threads add their messages to, without having to
+//! wait for each other or for the threads which flush the messages.
+use crate::buffer::{record_size, BufferLimits, LogBuffer, Record};
+use crate::timestamp::MonotonicTimestamps;
+use crate::Stream;
+use crossbeam_queue::SegQueue;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The [`LogQueue`] collects the logging messages of all threads in a lock-free queue until they
+/// get flushed.
+///
+/// As long as the limits of the buffer are not reached, adding a message never takes a lock. If
+/// one of the limits is reached, the queued messages are moved into the [`LogBuffer`], which applies
+/// the configured overflow policy. The threads which flush the messages only hold the lock of the
+/// buffer while taking the messages out of it, the serialization happens afterwards.
+pub(crate) struct LogQueue {
+    /// The messages which were logged but not moved into the buffer yet
+    queue: SegQueue<Record>,
+    /// The number of messages in the queue
+    queued_records: AtomicUsize,
+    /// The estimated number of bytes used by the messages in the queue
+    queued_bytes: AtomicUsize,
+    /// The buffer which applies the limits and groups the messages into streams
+    buffer: Mutex<LogBuffer>,
+    /// The number of messages in the buffer (updated every time the buffer was changed)
+    buffered_records: AtomicUsize,
+    /// The estimated number of bytes used by the messages in the buffer
+    buffered_bytes: AtomicUsize,
+    /// The limits of the buffer (checked without locking the buffer)
+    limits: BufferLimits,
+    /// Keeps the timestamps of every stream strictly increasing (if requested)
+    monotonic_timestamps: Option<Mutex<MonotonicTimestamps>>,
+}
+
+impl LogQueue {
+    /// Create a new, empty queue which applies the supplied limits (and makes the timestamps of
+    /// every stream strictly increasing, if requested).
+    pub(crate) fn new(limits: BufferLimits, monotonic_timestamps: bool) -> LogQueue {
+        LogQueue {
+            queue: SegQueue::new(),
+            queued_records: AtomicUsize::new(0),
+            queued_bytes: AtomicUsize::new(0),
+            buffer: Mutex::new(LogBuffer::with_limits(limits)),
+            buffered_records: AtomicUsize::new(0),
+            buffered_bytes: AtomicUsize::new(0),
+            limits,
+            monotonic_timestamps: if monotonic_timestamps {
+                Some(Mutex::new(MonotonicTimestamps::default()))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Add a logging message to the queue. Returns the number of pending messages and if a
+    /// message was dropped because the buffer was full.
+    ///
+    /// For [`crate::OverflowPolicy::Block`] nothing is dropped, instead the message is returned
+    /// so the caller can try again after the buffer was flushed (or drop it by calling
+    /// [`LogQueue::count_dropped`]).
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn push(&self, record: Record) -> Result<(usize, bool), Record> {
+        let size = record_size(&record.1, &record.2);
+
+        // the fast path, which does not need any lock
+        if let Some(pending) = self.try_reserve(size) {
+            self.queue.push(record);
+            return Ok((pending, false));
+        }
+
+        // the buffer is full, so the overflow policy has to decide which message is dropped
+        let mut buffer = self.buffer.lock();
+        let dropped = buffer.dropped();
+        self.drain(&mut buffer);
+        let result = buffer
+            .push(record.0, record.1, record.2)
+            .map(|()| (buffer.len(), buffer.dropped() > dropped));
+        self.update_buffered(&buffer);
+        result
+    }
+
+    /// Count a logging message which was dropped without trying to add it to the queue.
+    pub(crate) fn count_dropped(&self) {
+        self.buffer.lock().count_dropped();
+    }
+
+    /// Get the number of logging messages which were dropped because the buffer was full.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.buffer.lock().dropped()
+    }
+
+    /// Remove all pending logging messages and return them grouped into streams. The entries of
+    /// every stream are sorted by their timestamp, since the logging threads might have added them
+    /// in a different order.
+    pub(crate) fn take_streams(&self) -> Vec<Stream> {
+        let mut streams = {
+            let mut buffer = self.buffer.lock();
+            self.drain(&mut buffer);
+            let streams = buffer.take();
+            self.update_buffered(&buffer);
+            streams
+        };
+
+        for stream in streams.iter_mut() {
+            stream.values.sort_by_key(|entry| entry.timestamp);
+        }
+        if let Some(monotonic_timestamps) = &self.monotonic_timestamps {
+            monotonic_timestamps.lock().apply(&mut streams);
+        }
+        streams
+    }
+
+    /// Move all queued messages into the buffer. If the buffer rejects a message (because it is
+    /// full and should block), the message is queued again and sent with the next flush.
+    ///
+    /// The size of the buffer is published before a message is removed from the queue's counters,
+    /// so the messages are never missing from both of them (which would allow exceeding the limits).
+    fn drain(&self, buffer: &mut LogBuffer) {
+        let mut remaining = self.queued_records.load(Ordering::Acquire);
+        while remaining > 0 {
+            let record = match self.queue.pop() {
+                Some(record) => record,
+                None => break,
+            };
+            remaining -= 1;
+            let size = record_size(&record.1, &record.2);
+            if let Err(rejected) = buffer.push(record.0, record.1, record.2) {
+                self.queue.push(rejected);
+                break;
+            }
+            self.update_buffered(buffer);
+            self.queued_records.fetch_sub(1, Ordering::AcqRel);
+            self.queued_bytes.fetch_sub(size, Ordering::AcqRel);
+        }
+    }
+
+    /// Publish the size of the buffer, so it can be checked without locking the buffer.
+    fn update_buffered(&self, buffer: &LogBuffer) {
+        self.buffered_records.store(buffer.len(), Ordering::Release);
+        self.buffered_bytes.store(buffer.bytes(), Ordering::Release);
+    }
+
+    /// Reserve the space for a message with the supplied size in the queue. Returns the number of
+    /// pending messages (including the new one), or `None` if the message would exceed one of the
+    /// limits (in which case nothing is reserved).
+    ///
+    /// The space is reserved before checking the limits, so concurrent logging threads cannot
+    /// exceed them by checking at the same time.
+    fn try_reserve(&self, size: usize) -> Option<usize> {
+        let records = self.queued_records.fetch_add(1, Ordering::AcqRel)
+            + 1
+            + self.buffered_records.load(Ordering::Acquire);
+        let bytes = self.queued_bytes.fetch_add(size, Ordering::AcqRel)
+            + size
+            + self.buffered_bytes.load(Ordering::Acquire);
+        if self
+            .limits
+            .max_records
+            .is_some_and(|max_records| records > max_records)
+            || self
+                .limits
+                .max_bytes
+                .is_some_and(|max_bytes| bytes > max_bytes)
+        {
+            self.queued_records.fetch_sub(1, Ordering::AcqRel);
+            self.queued_bytes.fetch_sub(size, Ordering::AcqRel);
+            return None;
+        }
+        Some(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::BufferLimits;
+    use crate::queue::LogQueue;
+    use crate::{Entry, OverflowPolicy};
+    use log::Level;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Barrier};
+
+    fn record(line: &str, timestamp: u128) -> (Option<String>, BTreeMap<String, String>, Entry) {
+        let entry = Entry {
+            timestamp,
+            line: line.to_string(),
+            metadata: BTreeMap::new(),
+            level: Level::Info,
+        };
+        (None, BTreeMap::new(), entry)
+    }
+
+    fn lines(queue: &LogQueue) -> Vec<String> {
+        queue
+            .take_streams()
+            .iter()
+            .flat_map(|stream| stream.values.iter())
+            .map(|entry| entry.line.clone())
+            .collect()
+    }
+
+    #[test]
+    fn messages_of_concurrent_threads_are_all_queued() {
+        let queue = Arc::new(LogQueue::new(BufferLimits::default(), false));
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    for index in 0..1000 {
+                        queue
+                            .push(record("Hello Loki", thread * 1000 + index))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let streams = queue.take_streams();
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].values.len(), 8000);
+        assert!(queue.take_streams().is_empty());
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn the_overflow_policy_is_applied_to_the_queued_messages() {
+        let queue = LogQueue::new(
+            BufferLimits {
+                max_records: Some(2),
+                max_bytes: None,
+                overflow_policy: OverflowPolicy::DropOldest,
+            },
+            false,
+        );
+        assert_eq!(queue.push(record("first", 0)), Ok((1, false)));
+        assert_eq!(queue.push(record("second", 1)), Ok((2, false)));
+        assert_eq!(queue.push(record("third", 2)), Ok((2, true)));
+
+        assert_eq!(lines(&queue), vec!["second", "third"]);
+        assert_eq!(queue.dropped(), 1);
+    }
+
+    #[test]
+    fn concurrent_threads_never_exceed_the_limit() {
+        for _ in 0..200 {
+            let queue = Arc::new(LogQueue::new(
+                BufferLimits {
+                    max_records: Some(100),
+                    max_bytes: None,
+                    overflow_policy: OverflowPolicy::DropNewest,
+                },
+                false,
+            ));
+            for index in 0..96 {
+                queue.push(record("Hello Loki", index)).unwrap();
+            }
+
+            // all threads try to add the last messages at the same time
+            let barrier = Arc::new(Barrier::new(8));
+            let threads: Vec<_> = (0..8)
+                .map(|thread| {
+                    let queue = queue.clone();
+                    let barrier = barrier.clone();
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        queue.push(record("Hello Loki", 96 + thread)).unwrap();
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+
+            let pending = queue.queue.len() + queue.buffer.lock().len();
+            assert!(pending <= 100, "{} messages are pending", pending);
+            assert_eq!(pending as u64 + queue.dropped(), 104);
+        }
+    }
+
+    #[test]
+    fn messages_are_rejected_if_the_queue_is_full_and_should_block() {
+        let queue = LogQueue::new(
+            BufferLimits {
+                max_records: Some(1),
+                max_bytes: None,
+                overflow_policy: OverflowPolicy::Block,
+            },
+            false,
+        );
+        queue.push(record("first", 0)).unwrap();
+        let (_, _, rejected) = queue.push(record("second", 1)).unwrap_err();
+        assert_eq!(rejected.line, "second");
+
+        assert_eq!(lines(&queue), vec!["first"]);
+        assert_eq!(queue.push(record("second", 1)), Ok((1, false)));
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn the_entries_of_the_streams_are_ordered_by_their_timestamp() {
+        let queue = LogQueue::new(BufferLimits::default(), true);
+        queue.push(record("second", 2)).unwrap();
+        queue.push(record("first", 1)).unwrap();
+        queue.push(record("third", 2)).unwrap();
+
+        let streams = queue.take_streams();
+        let entries: Vec<(&str, u128)> = streams[0]
+            .values
+            .iter()
+            .map(|entry| (entry.line.as_str(), entry.timestamp))
+            .collect();
+        assert_eq!(entries, vec![("first", 1), ("second", 2), ("third", 3)]);
+    }
+}
@@ -1,68 +1,93 @@
-//! A module which contains the implementation for the [`FenrirBackend`] trait which uses the `reqwest`
-//! crate for network communication.
+//! A module which contains the implementation for the [`AsyncFenrirBackend`] trait which uses the
+//! `reqwest` crate for network communication.
 
-use crate::{AuthenticationMethod, FenrirBackend};
+use crate::retry::parse_retry_after;
+use crate::{
+    authorization_header, AsyncFenrirBackend, AuthenticationMethod, FenrirError, Payload,
+    RetryPolicy, SendFuture, TokenProvider,
+};
 use reqwest::Client;
 use std::any::TypeId;
 use url::Url;
 
-/// A [`FenrirBackend`] implementation which uses the [reqwest](https://crates.io/crates/reqwest) crate to
+/// A [`AsyncFenrirBackend`] implementation which uses the [reqwest](https://crates.io/crates/reqwest) crate to
 /// send logging messages to a Loki endpoint.
 pub(crate) struct ReqwestBackend {
-    /// The loki endpoint which is used to send log information to
-    pub(crate) endpoint: Url,
-    /// The authentication method to use when sending the log messages to the remote [`UreqBackend::endpoint`]
+    /// The URL of the push endpoint which is used to send log information to
+    pub(crate) push_url: Url,
+    /// The authentication method to use when sending the log messages to the remote [`ReqwestBackend::push_url`]
     pub(crate) authentication: AuthenticationMethod,
-    /// The credentials to use to authenticate against the remote [`UreqBackend::endpoint`]
+    /// The credentials to use to authenticate against the remote [`ReqwestBackend::push_url`]
     pub(crate) credentials: String,
+    /// The function providing the current bearer token (instead of the static credentials)
+    pub(crate) token_provider: Option<TokenProvider>,
+    /// Additional headers which are sent with every request
+    pub(crate) extra_headers: Vec<(String, String)>,
+    /// The policy for retrying requests which failed with a transient error
+    pub(crate) retry_policy: RetryPolicy,
     /// Internal client
     pub(crate) client: Client,
-    /// Runtime handle
-    pub(crate) runtime_handle: tokio::runtime::Handle,
 }
 
-impl FenrirBackend for ReqwestBackend {
-    fn send(&self, serialized_streams: Vec<u8>) -> Result<(), String> {
-        let post_url: Url = self
-            .endpoint
-            .clone()
-            .join("/loki/api/v1/push")
-            .map_err(|e| e.to_string())?;
+impl AsyncFenrirBackend for ReqwestBackend {
+    fn send(&self, payload: Payload) -> SendFuture {
         let mut builder = self
             .client
-            .post(post_url)
-            .header("Content-Type", "application/json; charset=utf-8");
-        if let AuthenticationMethod::Basic = self.authentication {
-            builder = builder.header(
-                "Authorization",
-                format!("Basic {}", self.credentials).as_str(),
-            );
+            .post(self.push_url.clone())
+            .header("Content-Type", payload.content_type());
+        if let Some(content_encoding) = payload.content_encoding() {
+            builder = builder.header("Content-Encoding", content_encoding);
         }
-        builder = builder.body(serialized_streams);
-        let runtime_handle = self.runtime_handle.clone();
-        runtime_handle.spawn(async move {
-            // retry 3 times if failed with a non-400 error
-            let mut retry_count = 0;
+        if let Some(tenant) = payload.tenant() {
+            builder = builder.header("X-Scope-OrgID", tenant);
+        }
+        for (name, value) in &self.extra_headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        builder = builder.body(payload.into_body());
+        let retry_policy = self.retry_policy.clone();
+        let authentication = self.authentication.clone();
+        let credentials = self.credentials.clone();
+        let token_provider = self.token_provider.clone();
+        Box::pin(async move {
+            let mut attempt = 1;
             loop {
-                let b2 = builder.try_clone().expect("should be able to clone");
-                let res = builder.send().await;
-                match res {
-                    Ok(_) => {
-                        break;
-                    }
-                    Err(e) => {
-                        if e.status().map_or(false, |x| x.is_client_error()) || retry_count >= 3 {
-                            log::error!("Failed to send logs to Loki: {}", e);
-                            break;
+                // a short-lived token might expire while retrying, so it is requested for every
+                // attempt
+                let mut request = builder.try_clone().expect("should be able to clone");
+                let sent = match authorization_header(
+                    &authentication,
+                    &credentials,
+                    token_provider.as_ref(),
+                ) {
+                    Ok(authorization) => {
+                        if let Some(authorization) = authorization {
+                            request = request.header("Authorization", authorization);
                         }
-                        retry_count += 1;
+                        request.send().await.map_err(map_error)
                     }
+                    Err(e) => Err(e),
+                };
+                let error = match sent {
+                    Ok(response) if response.status().is_success() => return Ok(()),
+                    Ok(response) => FenrirError::Http {
+                        status: response.status().as_u16(),
+                        retry_after: response
+                            .headers()
+                            .get("Retry-After")
+                            .and_then(|value| value.to_str().ok())
+                            .and_then(parse_retry_after),
+                        body: response.text().await.unwrap_or_default(),
+                    },
+                    Err(e) => e,
+                };
+                match retry_policy.delay_for(attempt, &error) {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => return Err(error),
                 }
-                builder = b2;
+                attempt += 1;
             }
-        });
-
-        Ok(())
+        })
     }
 
     fn internal_type(&self) -> TypeId {
@@ -76,11 +101,44 @@ impl FenrirBackend for ReqwestBackend {
     }
 
     fn credentials(&self) -> Option<String> {
-        if self.credentials.len() > 0 {
+        if !self.credentials.is_empty() {
             return Some(self.credentials.clone());
         }
         None
     }
+
+    fn ignored_targets(&self) -> Vec<String> {
+        [
+            "reqwest",
+            "hyper",
+            "hyper_util",
+            "h2",
+            "http",
+            "rustls",
+            "tokio_rustls",
+            "hyper_rustls",
+            "native_tls",
+            "tokio_native_tls",
+            "hyper_tls",
+            "want",
+            "mio",
+            "tokio",
+            "tokio_util",
+            "tower",
+        ]
+        .iter()
+        .map(|target| target.to_string())
+        .collect()
+    }
+}
+
+/// Convert an error returned by `reqwest` into the corresponding [`FenrirError`].
+fn map_error(error: reqwest::Error) -> FenrirError {
+    if error.is_timeout() {
+        FenrirError::Timeout(Box::new(error))
+    } else {
+        FenrirError::Transport(Box::new(error))
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,274 @@
+//! A module which contains the policy for retrying requests which failed with a transient error.
+use crate::FenrirError;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// The [`RetryPolicy`] configures how often and after which delay the backends retry sending
+/// logging messages if the request failed with a transient error (timeouts, connection errors,
+/// `429 Too Many Requests` and `5xx` responses).
+///
+/// The delay between the attempts grows exponentially, starting with the base delay and limited
+/// by the maximum delay. If the remote endpoint responds with a `Retry-After` header, the
+/// requested delay is used instead (but still limited by the maximum delay).
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use fenrir_rs::RetryPolicy;
+///
+/// let policy = RetryPolicy::new(5)
+///     .base_delay(Duration::from_millis(200))
+///     .max_delay(Duration::from_secs(10))
+///     .jitter(true);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts (including the first one)
+    max_attempts: u32,
+    /// The delay before the first retry
+    base_delay: Duration,
+    /// The upper limit for the delay between two attempts
+    max_delay: Duration,
+    /// If set to `true`, the delays are randomized to avoid that several clients retry in lockstep
+    jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    /// The default policy does up to 4 attempts, starting with a delay of 500 ms and a maximum
+    /// delay of 30 seconds (with jitter).
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Create a new policy with the supplied maximum number of attempts (including the first one)
+    /// and the default delays.
+    pub fn new(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Create a policy which does not retry failed requests at all.
+    pub fn never() -> RetryPolicy {
+        RetryPolicy::new(1)
+    }
+
+    /// Set the delay before the first retry.
+    pub fn base_delay(mut self, delay: Duration) -> RetryPolicy {
+        self.base_delay = delay;
+        self
+    }
+
+    /// Set the upper limit for the delay between two attempts.
+    pub fn max_delay(mut self, delay: Duration) -> RetryPolicy {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Enable or disable the randomization of the delays.
+    pub fn jitter(mut self, enabled: bool) -> RetryPolicy {
+        self.jitter = enabled;
+        self
+    }
+
+    /// Get the maximum number of attempts (including the first one).
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Check if a request which failed with the supplied error should be retried at all.
+    pub fn is_retryable(error: &FenrirError) -> bool {
+        match error {
+            FenrirError::Timeout(_) | FenrirError::Transport(_) => true,
+            FenrirError::Http { status, .. } => matches!(status, 408 | 429 | 500..=599),
+            _ => false,
+        }
+    }
+
+    /// Get the delay to wait before the next attempt, after the `attempt`th attempt (starting
+    /// with 1) failed with the supplied `error`. Returns `None` if the request should not be
+    /// retried anymore.
+    ///
+    /// This can be used by implementations of custom backends to apply the configured policy.
+    pub fn delay_for(&self, attempt: u32, error: &FenrirError) -> Option<Duration> {
+        if attempt >= self.max_attempts || !RetryPolicy::is_retryable(error) {
+            return None;
+        }
+
+        // honour the delay the remote endpoint asked us to wait
+        if let FenrirError::Http {
+            retry_after: Some(retry_after),
+            ..
+        } = error
+        {
+            return Some((*retry_after).min(self.max_delay));
+        }
+
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        if !self.jitter {
+            return Some(delay);
+        }
+
+        // use half of the delay as a fixed part and randomize the other half
+        let half = delay / 2;
+        Some(half + half.mul_f64(random_fraction()))
+    }
+
+    /// Call `send` until it succeeds, fails with an error which should not be retried or the
+    /// maximum number of attempts was reached. The current thread is blocked between the attempts.
+    ///
+    /// This can be used by implementations of custom backends to apply the configured policy.
+    pub fn retry_blocking<F>(&self, mut send: F) -> Result<(), FenrirError>
+    where
+        F: FnMut() -> Result<(), FenrirError>,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match send() {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+            match self.delay_for(attempt, &error) {
+                Some(delay) => std::thread::sleep(delay),
+                None => return Err(error),
+            }
+            attempt += 1;
+        }
+    }
+}
+
+/// Parse the value of a `Retry-After` header. Only the delay in seconds is supported, since
+/// this is what Loki and most proxies send.
+#[cfg(any(feature = "ureq", feature = "reqwest-async"))]
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// Get a random number in the range `[0, 1)` without requiring an additional dependency. The
+/// quality of the randomness is good enough for spreading the retries of several clients.
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "ureq", feature = "reqwest-async"))]
+    use crate::retry::parse_retry_after;
+    use crate::retry::RetryPolicy;
+    use crate::FenrirError;
+    use std::time::Duration;
+
+    fn unavailable(retry_after: Option<Duration>) -> FenrirError {
+        FenrirError::Http {
+            status: 503,
+            body: String::new(),
+            retry_after,
+        }
+    }
+
+    #[test]
+    fn the_delay_grows_exponentially_up_to_the_maximum() {
+        let policy = RetryPolicy::new(10)
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(500))
+            .jitter(false);
+        let error = unavailable(None);
+
+        assert_eq!(
+            policy.delay_for(1, &error),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            policy.delay_for(2, &error),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(
+            policy.delay_for(3, &error),
+            Some(Duration::from_millis(400))
+        );
+        assert_eq!(
+            policy.delay_for(4, &error),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(policy.delay_for(10, &error), None);
+    }
+
+    #[test]
+    fn the_jitter_keeps_the_delay_within_the_expected_range() {
+        let policy = RetryPolicy::new(3).base_delay(Duration::from_millis(100));
+        let delay = policy.delay_for(1, &unavailable(None)).unwrap();
+        assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn the_retry_after_header_is_honoured() {
+        let policy = RetryPolicy::new(3).max_delay(Duration::from_secs(10));
+        assert_eq!(
+            policy.delay_for(1, &unavailable(Some(Duration::from_secs(2)))),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            policy.delay_for(1, &unavailable(Some(Duration::from_secs(60)))),
+            Some(Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    #[cfg(any(feature = "ureq", feature = "reqwest-async"))]
+    fn the_retry_after_header_is_parsed_as_seconds() {
+        assert_eq!(parse_retry_after(" 5 "), Some(Duration::from_secs(5)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        let error = FenrirError::Http {
+            status: 400,
+            body: "entry out of order".to_string(),
+            retry_after: None,
+        };
+        assert_eq!(RetryPolicy::default().delay_for(1, &error), None);
+        assert!(RetryPolicy::is_retryable(&FenrirError::Http {
+            status: 429,
+            body: String::new(),
+            retry_after: None,
+        }));
+    }
+
+    #[test]
+    fn sending_is_retried_until_it_succeeds() {
+        let policy = RetryPolicy::new(3).base_delay(Duration::from_millis(1));
+        let mut attempts = 0;
+        let result = policy.retry_blocking(|| {
+            attempts += 1;
+            if attempts < 3 {
+                Err(unavailable(None))
+            } else {
+                Ok(())
+            }
+        });
+        assert!(result.is_ok());
+        assert_eq!(attempts, 3);
+    }
+}
@@ -0,0 +1,259 @@
+//! A module which contains the adapter for running an [`AsyncFenrirBackend`] on a tokio runtime.
+use crate::guard::Guarded;
+use crate::{AsyncFenrirBackend, AuthenticationMethod, FenrirBackend, FenrirError, Payload};
+use parking_lot::{Condvar, Mutex};
+use std::any::TypeId;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A [`FenrirBackend`] implementation which spawns the futures created by an [`AsyncFenrirBackend`]
+/// on a tokio runtime, so the thread which flushes the messages does not have to wait for them.
+pub(crate) struct TokioBackend {
+    /// The backend which creates the futures for sending the logging messages
+    backend: Arc<dyn AsyncFenrirBackend>,
+    /// The handle of the runtime which is used for running the futures
+    runtime_handle: tokio::runtime::Handle,
+    /// The requests which were spawned but are not finished yet
+    pending: Arc<PendingRequests>,
+}
+
+impl TokioBackend {
+    /// Create a new backend which runs the futures of the supplied backend on the runtime.
+    pub(crate) fn new(
+        backend: Arc<dyn AsyncFenrirBackend>,
+        runtime_handle: tokio::runtime::Handle,
+    ) -> TokioBackend {
+        TokioBackend {
+            backend,
+            runtime_handle,
+            pending: Arc::new(PendingRequests::default()),
+        }
+    }
+}
+
+impl FenrirBackend for TokioBackend {
+    fn send(&self, payload: Payload) -> Result<(), FenrirError> {
+        let request = self.backend.send(payload);
+        let pending = self.pending.clone();
+        pending.start();
+        // the future runs on a thread of the runtime, so the messages logged while it is polled
+        // have to be ignored as well
+        self.runtime_handle.spawn(Guarded(Box::pin(async move {
+            // logging the error would be suppressed by the guard, so it has to be printed
+            if let Err(e) = request.await {
+                eprintln!("fenrir: Failed to send logs to Loki: {}", e);
+            }
+            pending.finish();
+        })));
+        Ok(())
+    }
+
+    fn internal_type(&self) -> TypeId {
+        self.backend.internal_type()
+    }
+
+    fn authentication_method(&self) -> AuthenticationMethod {
+        self.backend.authentication_method()
+    }
+
+    fn credentials(&self) -> Option<String> {
+        self.backend.credentials()
+    }
+
+    fn ignored_targets(&self) -> Vec<String> {
+        self.backend.ignored_targets()
+    }
+
+    fn wait_for_pending(&self, timeout: Duration) -> bool {
+        self.pending.wait(timeout)
+    }
+}
+
+/// A [`FenrirBackend`] implementation which waits for the futures created by an
+/// [`AsyncFenrirBackend`] to finish, so the actual result of the request can be reported (e.g. by
+/// the [`crate::FenrirHandle`]).
+///
+/// Since [`tokio::runtime::Handle::block_on`] is used, the backend must not be used on a thread of
+/// the runtime itself (use [`tokio::task::spawn_blocking`] instead).
+pub(crate) struct BlockingBackend {
+    /// The backend which creates the futures for sending the logging messages
+    backend: Arc<dyn AsyncFenrirBackend>,
+    /// The handle of the runtime which is used for running the futures
+    runtime_handle: tokio::runtime::Handle,
+}
+
+impl BlockingBackend {
+    /// Create a new backend which runs the futures of the supplied backend on the runtime.
+    pub(crate) fn new(
+        backend: Arc<dyn AsyncFenrirBackend>,
+        runtime_handle: tokio::runtime::Handle,
+    ) -> BlockingBackend {
+        BlockingBackend {
+            backend,
+            runtime_handle,
+        }
+    }
+}
+
+impl FenrirBackend for BlockingBackend {
+    fn send(&self, payload: Payload) -> Result<(), FenrirError> {
+        self.runtime_handle.block_on(self.backend.send(payload))
+    }
+
+    fn internal_type(&self) -> TypeId {
+        self.backend.internal_type()
+    }
+
+    fn authentication_method(&self) -> AuthenticationMethod {
+        self.backend.authentication_method()
+    }
+
+    fn credentials(&self) -> Option<String> {
+        self.backend.credentials()
+    }
+
+    fn ignored_targets(&self) -> Vec<String> {
+        self.backend.ignored_targets()
+    }
+}
+
+/// The [`PendingRequests`] count the requests which were spawned on the runtime but are not
+/// finished yet, so it is possible to wait for them (e.g. before the application exits).
+#[derive(Default)]
+struct PendingRequests {
+    count: Mutex<usize>,
+    /// Notified every time the last pending request finished
+    finished: Condvar,
+}
+
+impl PendingRequests {
+    /// Mark a new request as pending.
+    fn start(&self) {
+        *self.count.lock() += 1;
+    }
+
+    /// Mark a pending request as finished.
+    fn finish(&self) {
+        let mut count = self.count.lock();
+        *count -= 1;
+        if *count == 0 {
+            self.finished.notify_all();
+        }
+    }
+
+    /// Wait until all pending requests are finished, returns `false` if the supplied timeout
+    /// elapsed before.
+    fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut count = self.count.lock();
+        while *count > 0 {
+            if self.finished.wait_until(&mut count, deadline).timed_out() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::{BlockingBackend, TokioBackend};
+    use crate::{guard, AsyncFenrirBackend, FenrirBackend, FenrirError, Payload, SendFuture};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// A backend which needs the supplied time for sending a payload (and rejects empty payloads)
+    struct SlowBackend(Duration);
+
+    impl AsyncFenrirBackend for SlowBackend {
+        fn send(&self, payload: Payload) -> SendFuture {
+            let delay = self.0;
+            Box::pin(async move {
+                tokio::time::sleep(delay).await;
+                if payload.body().is_empty() {
+                    return Err(FenrirError::Other("empty payload".to_string()));
+                }
+                Ok(())
+            })
+        }
+
+        fn internal_type(&self) -> std::any::TypeId {
+            std::any::TypeId::of::<Self>()
+        }
+    }
+
+    #[test]
+    fn waiting_for_pending_requests_returns_after_they_are_finished() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let backend = TokioBackend::new(
+            Arc::new(SlowBackend(Duration::from_millis(200))),
+            runtime.handle().clone(),
+        );
+        assert!(backend.wait_for_pending(Duration::ZERO));
+
+        backend
+            .send(Payload::new(vec![], "application/json"))
+            .unwrap();
+        assert!(!backend.wait_for_pending(Duration::from_millis(10)));
+        assert!(backend.wait_for_pending(Duration::from_secs(10)));
+    }
+
+    /// A backend which fails every request and records if logging was suppressed while doing so
+    #[derive(Clone, Default)]
+    struct FailingBackend(Arc<AtomicBool>);
+
+    impl AsyncFenrirBackend for FailingBackend {
+        fn send(&self, _payload: Payload) -> SendFuture {
+            let suppressed = self.0.clone();
+            Box::pin(async move {
+                suppressed.store(guard::is_active(), Ordering::SeqCst);
+                Err(FenrirError::Other("unavailable".to_string()))
+            })
+        }
+
+        fn internal_type(&self) -> std::any::TypeId {
+            std::any::TypeId::of::<Self>()
+        }
+    }
+
+    #[test]
+    fn a_failed_request_is_reported_and_finished_while_logging_is_suppressed() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let failing = FailingBackend::default();
+        let backend = TokioBackend::new(Arc::new(failing.clone()), runtime.handle().clone());
+
+        // the error only surfaces on the runtime, the request itself is accepted
+        assert!(backend
+            .send(Payload::new(b"{}".to_vec(), "application/json"))
+            .is_ok());
+        assert!(backend.wait_for_pending(Duration::from_secs(10)));
+        assert!(failing.0.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn the_blocking_backend_reports_the_result_of_the_request() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let backend = BlockingBackend::new(
+            Arc::new(SlowBackend(Duration::from_millis(10))),
+            runtime.handle().clone(),
+        );
+
+        assert!(backend
+            .send(Payload::new(b"{}".to_vec(), "application/json"))
+            .is_ok());
+        assert!(matches!(
+            backend.send(Payload::new(vec![], "application/json")),
+            Err(FenrirError::Other(_))
+        ));
+    }
+}
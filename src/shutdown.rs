@@ -0,0 +1,66 @@
+//! A module which contains the graceful shutdown, which sends all buffered logging messages to Loki
+//! and waits until they were delivered (e.g. before the application exits).
+use crate::worker::FlushHandle;
+use crate::FenrirBackend;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The [`ShutdownGuard`] sends all buffered logging messages to Loki and waits until they were
+/// delivered (or the configured timeout elapsed) when it gets dropped. It is created by
+/// [`crate::Fenrir::shutdown_guard`] and should be kept alive until the end of the `main` function,
+/// even if the [`crate::Fenrir`] instance itself was moved into the `log` crate.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use fenrir_rs::{Fenrir, NetworkingBackend, SerializationFormat};
+///
+/// let fenrir = Fenrir::builder()
+///     .network(NetworkingBackend::Ureq)
+///     .format(SerializationFormat::Json)
+///     .build();
+/// let _guard = fenrir.shutdown_guard(Duration::from_secs(5));
+/// log::set_boxed_logger(Box::new(fenrir)).unwrap();
+/// ```
+#[must_use = "the messages are flushed when the guard is dropped"]
+pub struct ShutdownGuard {
+    /// The handle of the background worker which flushes the messages
+    worker: FlushHandle,
+    /// The backend which is used for sending the messages
+    backend: Arc<dyn FenrirBackend>,
+    /// The maximum time to wait for the messages to be delivered
+    timeout: Duration,
+}
+
+impl ShutdownGuard {
+    /// Create a new guard for the supplied background worker and backend.
+    pub(crate) fn new(
+        worker: FlushHandle,
+        backend: Arc<dyn FenrirBackend>,
+        timeout: Duration,
+    ) -> ShutdownGuard {
+        ShutdownGuard {
+            worker,
+            backend,
+            timeout,
+        }
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        flush_and_wait(&self.worker, self.backend.as_ref(), self.timeout);
+    }
+}
+
+/// Flush all buffered messages using the background worker and wait until all requests of the
+/// backend are finished. Returns `false` if the supplied timeout elapsed before.
+pub(crate) fn flush_and_wait(
+    worker: &FlushHandle,
+    backend: &dyn FenrirBackend,
+    timeout: Duration,
+) -> bool {
+    let deadline = Instant::now() + timeout;
+    worker.request_flush_and_wait(timeout)
+        && backend.wait_for_pending(deadline.saturating_duration_since(Instant::now()))
+}
@@ -0,0 +1,321 @@
+//! A module which contains the on-disk spool for logging messages which could not be delivered to
+//! Loki, so they can be sent later on (even after the process was restarted).
+use crate::{Compression, FenrirBackend, FenrirError, Payload, RetryPolicy, SerializationFormat};
+use parking_lot::Mutex;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The file extension used for the segment files of the spool
+const SEGMENT_EXTENSION: &str = "spool";
+
+/// The first line of every segment file, used to recognize the file format
+const SEGMENT_MAGIC: &str = "fenrir-spool-v1";
+
+/// The [`Spool`] stores serialized batches which could not be sent in segment files (one file per
+/// batch) inside a directory. The files are named by an increasing sequence number, so they can be
+/// replayed in the same order they were stored.
+pub(crate) struct Spool {
+    /// The directory which contains the segment files
+    directory: PathBuf,
+    /// The maximum number of bytes all segment files are allowed to use, the oldest segments are
+    /// removed if the limit is exceeded
+    max_bytes: u64,
+    /// The sequence number of the next segment, the lock is also used to serialize the access to
+    /// the directory
+    next_sequence: Mutex<u64>,
+}
+
+impl Spool {
+    /// Open the spool in the supplied directory (which is created if it does not exist yet).
+    pub(crate) fn open(directory: &Path, max_bytes: u64) -> io::Result<Spool> {
+        fs::create_dir_all(directory)?;
+        let spool = Spool {
+            directory: directory.to_path_buf(),
+            max_bytes,
+            next_sequence: Mutex::new(0),
+        };
+        let next_sequence = spool
+            .segments()?
+            .last()
+            .map_or(0, |(sequence, _)| sequence + 1);
+        *spool.next_sequence.lock() = next_sequence;
+        Ok(spool)
+    }
+
+    /// Append the supplied payload as a new segment to the spool.
+    pub(crate) fn store(&self, payload: &Payload) -> io::Result<()> {
+        let mut next_sequence = self.next_sequence.lock();
+
+        // write the segment to a temporary file first, so an incomplete segment is never replayed
+        let mut header = format!(
+            "{}\ncontent-type: {}\n",
+            SEGMENT_MAGIC,
+            payload.content_type()
+        );
+        if let Some(tenant) = payload.tenant() {
+            header.push_str(&format!("tenant: {}\n", tenant));
+        }
+        if let Some(content_encoding) = payload.content_encoding() {
+            header.push_str(&format!("content-encoding: {}\n", content_encoding));
+        }
+        header.push('\n');
+        let mut content = header.into_bytes();
+        content.extend_from_slice(payload.body());
+        let path = self.segment_path(*next_sequence);
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, content)?;
+        fs::rename(&temporary_path, &path)?;
+        *next_sequence += 1;
+
+        // remove the oldest segments until the spool fits into the configured size again
+        let segments = self.segments()?;
+        let mut total_bytes: u64 = segments.iter().map(|(_, size)| size).sum();
+        for (sequence, size) in segments {
+            if total_bytes <= self.max_bytes {
+                break;
+            }
+            fs::remove_file(self.segment_path(sequence))?;
+            total_bytes -= size;
+        }
+        Ok(())
+    }
+
+    /// Send all stored segments in order using the supplied backend. Every segment which was sent
+    /// successfully is removed from the spool, the replay stops at the first segment which could not
+    /// be sent because of a temporary error (to keep the order of the messages). Segments which
+    /// were rejected by Loki would be rejected again, so they are removed as well.
+    pub(crate) fn replay(&self, backend: &dyn FenrirBackend) -> Result<(), FenrirError> {
+        let _guard = self.next_sequence.lock();
+        let segments = self
+            .segments()
+            .map_err(|error| FenrirError::Other(error.to_string()))?;
+        for (sequence, _) in segments {
+            // segments which cannot be read are corrupt and just get removed
+            let path = self.segment_path(sequence);
+            if let Some(payload) = read_segment(&path) {
+                match backend.send(payload) {
+                    Err(error) if RetryPolicy::is_retryable(&error) => return Err(error),
+                    Err(error) => eprintln!(
+                        "fenrir: Loki rejected the spooled logs, they are dropped. The error was: {}",
+                        error
+                    ),
+                    Ok(()) => {}
+                }
+            }
+            fs::remove_file(&path).map_err(|error| FenrirError::Other(error.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Get the sequence numbers and sizes of all stored segments, ordered by the sequence number.
+    fn segments(&self) -> io::Result<Vec<(u64, u64)>> {
+        let mut segments = vec![];
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(SEGMENT_EXTENSION)
+            {
+                continue;
+            }
+            let sequence = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok());
+            if let Some(sequence) = sequence {
+                segments.push((sequence, fs::metadata(&path)?.len()));
+            }
+        }
+        segments.sort_unstable();
+        Ok(segments)
+    }
+
+    /// Get the path of the segment with the supplied sequence number.
+    fn segment_path(&self, sequence: u64) -> PathBuf {
+        self.directory
+            .join(format!("{:020}.{}", sequence, SEGMENT_EXTENSION))
+    }
+}
+
+/// Read the payload stored in a segment file, returns `None` if the file is not a valid segment.
+fn read_segment(path: &Path) -> Option<Payload> {
+    let content = fs::read(path).ok()?;
+    let header_end = content.windows(2).position(|window| window == b"\n\n")?;
+    let header = std::str::from_utf8(&content[..header_end]).ok()?;
+
+    let mut lines = header.lines();
+    if lines.next() != Some(SEGMENT_MAGIC) {
+        return None;
+    }
+    let mut content_type = None;
+    let mut tenant = None;
+    let mut content_encoding = None;
+    for line in lines {
+        if let Some(value) = line.strip_prefix("content-type: ") {
+            content_type = SerializationFormat::static_content_type(value);
+        } else if let Some(value) = line.strip_prefix("tenant: ") {
+            tenant = Some(value);
+        } else if let Some(value) = line.strip_prefix("content-encoding: ") {
+            // segments compressed with a method which is not available anymore cannot be sent
+            content_encoding = Some(Compression::static_content_encoding(value)?);
+        }
+    }
+
+    let mut payload = Payload::new(content[header_end + 2..].to_vec(), content_type?);
+    if let Some(tenant) = tenant {
+        payload = payload.with_tenant(tenant);
+    }
+    if let Some(content_encoding) = content_encoding {
+        payload = payload.with_content_encoding(content_encoding);
+    }
+    Some(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spool::Spool;
+    use crate::{FenrirBackend, FenrirError, Payload};
+    use parking_lot::Mutex;
+    use std::path::PathBuf;
+
+    /// A backend which records the bodies of all payloads or fails if requested
+    #[derive(Default)]
+    struct TestBackend {
+        fail: bool,
+        rejected_body: Option<&'static [u8]>,
+        bodies: Mutex<Vec<Vec<u8>>>,
+        tenants: Mutex<Vec<Option<String>>>,
+        content_encodings: Mutex<Vec<Option<&'static str>>>,
+    }
+
+    impl FenrirBackend for TestBackend {
+        fn send(&self, payload: Payload) -> Result<(), FenrirError> {
+            if self.fail {
+                return Err(FenrirError::Transport("unavailable".into()));
+            }
+            if self.rejected_body == Some(payload.body()) {
+                return Err(FenrirError::Http {
+                    status: 400,
+                    body: "entry out of order".to_string(),
+                    retry_after: None,
+                });
+            }
+            self.tenants
+                .lock()
+                .push(payload.tenant().map(str::to_string));
+            self.content_encodings
+                .lock()
+                .push(payload.content_encoding());
+            self.bodies.lock().push(payload.into_body());
+            Ok(())
+        }
+
+        fn internal_type(&self) -> std::any::TypeId {
+            std::any::TypeId::of::<Self>()
+        }
+    }
+
+    fn spool_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("fenrir-spool-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn payload(body: &str) -> Payload {
+        Payload::new(body.as_bytes().to_vec(), "application/octet-stream")
+    }
+
+    #[test]
+    fn spooled_payloads_are_replayed_in_order_after_reopening_the_spool() {
+        let directory = spool_directory("replay");
+        {
+            let spool = Spool::open(&directory, 1024).unwrap();
+            spool.store(&payload("first")).unwrap();
+            spool
+                .store(&payload("second").with_tenant("team-a"))
+                .unwrap();
+        }
+
+        let spool = Spool::open(&directory, 1024).unwrap();
+        spool.store(&payload("third")).unwrap();
+        assert!(spool
+            .replay(&TestBackend {
+                fail: true,
+                ..TestBackend::default()
+            })
+            .is_err());
+
+        let backend = TestBackend::default();
+        spool.replay(&backend).unwrap();
+        assert_eq!(
+            *backend.bodies.lock(),
+            vec![b"first".to_vec(), b"second".to_vec(), b"third".to_vec()]
+        );
+        assert_eq!(
+            *backend.tenants.lock(),
+            vec![None, Some("team-a".to_string()), None]
+        );
+
+        let backend = TestBackend::default();
+        spool.replay(&backend).unwrap();
+        assert!(backend.bodies.lock().is_empty());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rejected_payloads_do_not_block_the_replay() {
+        let directory = spool_directory("rejected");
+        let spool = Spool::open(&directory, 1024).unwrap();
+        spool.store(&payload("first")).unwrap();
+        spool.store(&payload("malformed")).unwrap();
+        spool.store(&payload("third")).unwrap();
+
+        let backend = TestBackend {
+            rejected_body: Some(b"malformed"),
+            ..TestBackend::default()
+        };
+        spool.replay(&backend).unwrap();
+        assert_eq!(
+            *backend.bodies.lock(),
+            vec![b"first".to_vec(), b"third".to_vec()]
+        );
+
+        let backend = TestBackend::default();
+        spool.replay(&backend).unwrap();
+        assert!(backend.bodies.lock().is_empty());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn the_oldest_payloads_are_removed_if_the_spool_is_full() {
+        let directory = spool_directory("full");
+        let spool = Spool::open(&directory, 160).unwrap();
+        spool.store(&payload("first")).unwrap();
+        spool.store(&payload("second")).unwrap();
+        spool.store(&payload("third")).unwrap();
+
+        let backend = TestBackend::default();
+        spool.replay(&backend).unwrap();
+        assert_eq!(
+            *backend.bodies.lock(),
+            vec![b"second".to_vec(), b"third".to_vec()]
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn the_content_encoding_of_spooled_payloads_is_kept() {
+        let directory = spool_directory("encoding");
+        let spool = Spool::open(&directory, 1024).unwrap();
+        spool.store(&payload("plain")).unwrap();
+        spool
+            .store(&payload("compressed").with_content_encoding("gzip"))
+            .unwrap();
+
+        let backend = TestBackend::default();
+        spool.replay(&backend).unwrap();
+        assert_eq!(*backend.content_encodings.lock(), vec![None, Some("gzip")]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+//! A module which contains everything related to the timestamps of the logging messages: the
+//! clock they are taken from, parsing timestamps supplied by the application and keeping the
+//! timestamps of a stream strictly increasing.
+use crate::buffer::fingerprint;
+use crate::Stream;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The time after which the last timestamp of a stream is forgotten. Loki accepts out-of-order
+/// entries within this window (half of the default `max_chunk_age`), so an older timestamp cannot
+/// cause a rejection anymore.
+const OUT_OF_ORDER_WINDOW: Duration = Duration::from_secs(3600);
+
+/// The function which is called for getting the timestamp of every logging message (see
+/// [`crate::FenrirBuilder::clock`]).
+pub type Clock = Arc<dyn Fn() -> SystemTime + Send + Sync>;
+
+/// Get the number of nanoseconds since the UNIX epoch of the supplied time. Times before the
+/// epoch (e.g. of a system whose clock was not set yet) are mapped to the epoch itself.
+pub(crate) fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos())
+}
+
+/// Parse a timestamp supplied by the application, either as the number of nanoseconds since the
+/// UNIX epoch (e.g. `1688652000123456789`) or as an RFC 3339 string (e.g.
+/// `2023-07-06T14:00:00.123456789+02:00`). Returns `None` if the value is neither of both.
+#[cfg(feature = "structured_logging")]
+pub(crate) fn parse_timestamp(value: &str) -> Option<u128> {
+    let value = value.trim();
+    if !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return value.parse().ok();
+    }
+    parse_rfc3339(value)
+}
+
+/// Parse an RFC 3339 timestamp into the number of nanoseconds since the UNIX epoch.
+#[cfg(feature = "structured_logging")]
+fn parse_rfc3339(value: &str) -> Option<u128> {
+    let bytes = value.as_bytes();
+    if bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = value.get(range)?;
+        if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    };
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    // the optional fraction of a second (only nanoseconds are kept)
+    let mut rest = &value[19..];
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let length = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if length == 0 {
+            return None;
+        }
+        let digits = &fraction[..length.min(9)];
+        nanos = digits.parse::<i64>().ok()? * 10_i64.pow(9 - digits.len() as u32);
+        rest = &fraction[length..];
+    }
+
+    // the offset to UTC, which has to be subtracted to get the UTC time
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            if rest.len() != 6 || rest.as_bytes()[3] != b':' {
+                return None;
+            }
+            let hours = rest[1..3].parse::<i64>().ok()?;
+            let minutes = rest[4..6].parse::<i64>().ok()?;
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    if seconds < 0 {
+        return Some(0);
+    }
+    Some(seconds as u128 * 1_000_000_000 + nanos as u128)
+}
+
+/// Get the number of days since the UNIX epoch of the supplied date (in the proleptic Gregorian
+/// calendar).
+#[cfg(feature = "structured_logging")]
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The [`MonotonicTimestamps`] ensure that the timestamps of the entries of every stream are
+/// strictly increasing, even across flushes, by moving entries with the same (or an earlier)
+/// timestamp as the previous entry one nanosecond after it.
+#[derive(Default)]
+pub(crate) struct MonotonicTimestamps {
+    /// The timestamp of the last entry which was flushed for every stream (kept for streams which
+    /// were not part of the latest flushes as well, until they are older than the
+    /// [`OUT_OF_ORDER_WINDOW`])
+    last: HashMap<u64, u128>,
+    /// The latest timestamp which was flushed for any stream
+    newest: u128,
+}
+
+impl MonotonicTimestamps {
+    /// Adjust the timestamps of the supplied streams, whose entries have to be sorted by their
+    /// timestamp already.
+    pub(crate) fn apply(&mut self, streams: &mut [Stream]) {
+        for stream in streams.iter_mut() {
+            let fingerprint = fingerprint(stream.tenant.as_deref(), &stream.stream);
+            let mut previous = self.last.get(&fingerprint).copied();
+            for entry in stream.values.iter_mut() {
+                if let Some(previous) = previous {
+                    if entry.timestamp <= previous {
+                        entry.timestamp = previous + 1;
+                    }
+                }
+                previous = Some(entry.timestamp);
+            }
+            if let Some(previous) = previous {
+                self.last.insert(fingerprint, previous);
+                self.newest = self.newest.max(previous);
+            }
+        }
+
+        // forget the streams which did not log anything for a long time, otherwise the map would
+        // grow with every label set which was ever used
+        let oldest = self.newest.saturating_sub(OUT_OF_ORDER_WINDOW.as_nanos());
+        self.last.retain(|_, last| *last >= oldest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "structured_logging")]
+    use crate::timestamp::parse_timestamp;
+    use crate::timestamp::{unix_nanos, MonotonicTimestamps, OUT_OF_ORDER_WINDOW};
+    use crate::{Entry, Stream};
+    use log::Level;
+    use std::collections::BTreeMap;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn stream(timestamps: &[u128]) -> Stream {
+        Stream {
+            tenant: None,
+            stream: BTreeMap::new(),
+            values: timestamps
+                .iter()
+                .map(|timestamp| Entry {
+                    timestamp: *timestamp,
+                    line: "Hello Loki".to_string(),
+                    metadata: BTreeMap::new(),
+                    level: Level::Info,
+                })
+                .collect(),
+        }
+    }
+
+    fn timestamps(stream: &Stream) -> Vec<u128> {
+        stream.values.iter().map(|entry| entry.timestamp).collect()
+    }
+
+    #[test]
+    fn times_before_the_epoch_do_not_panic() {
+        assert_eq!(unix_nanos(UNIX_EPOCH - Duration::from_secs(60)), 0);
+        assert_eq!(unix_nanos(UNIX_EPOCH + Duration::from_nanos(42)), 42);
+    }
+
+    #[test]
+    #[cfg(feature = "structured_logging")]
+    fn timestamps_are_parsed_as_nanoseconds_or_rfc3339() {
+        assert_eq!(
+            parse_timestamp("1688652000123456789"),
+            Some(1688652000123456789)
+        );
+        assert_eq!(
+            parse_timestamp("2023-07-06T14:00:00.123456789Z"),
+            Some(1688652000123456789)
+        );
+        assert_eq!(
+            parse_timestamp("2023-07-06T16:00:00.123+02:00"),
+            Some(1688652000123000000)
+        );
+        assert_eq!(parse_timestamp("1969-12-31T23:59:59Z"), Some(0));
+        assert_eq!(parse_timestamp("2023-13-06T14:00:00Z"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+        assert_eq!(parse_timestamp(""), None);
+    }
+
+    #[test]
+    fn the_timestamps_of_a_stream_are_strictly_increasing_across_flushes() {
+        let mut monotonic = MonotonicTimestamps::default();
+        let mut streams = vec![stream(&[10, 10, 10, 12])];
+        monotonic.apply(&mut streams);
+        assert_eq!(timestamps(&streams[0]), vec![10, 11, 12, 13]);
+
+        let mut streams = vec![stream(&[12, 20])];
+        monotonic.apply(&mut streams);
+        assert_eq!(timestamps(&streams[0]), vec![14, 20]);
+    }
+
+    #[test]
+    fn the_timestamps_of_a_stream_are_kept_if_it_skipped_a_flush() {
+        let mut monotonic = MonotonicTimestamps::default();
+        let mut streams = vec![stream(&[10, 20])];
+        monotonic.apply(&mut streams);
+
+        let mut other = stream(&[5]);
+        other
+            .stream
+            .insert("service".to_string(), "worker".to_string());
+        monotonic.apply(&mut [other]);
+
+        let mut streams = vec![stream(&[15])];
+        monotonic.apply(&mut streams);
+        assert_eq!(timestamps(&streams[0]), vec![21]);
+    }
+
+    #[test]
+    fn the_timestamps_of_streams_outside_of_the_out_of_order_window_are_forgotten() {
+        let mut monotonic = MonotonicTimestamps::default();
+        let mut streams = vec![stream(&[10])];
+        monotonic.apply(&mut streams);
+
+        let window = OUT_OF_ORDER_WINDOW.as_nanos();
+        let mut other = stream(&[11 + window]);
+        other
+            .stream
+            .insert("service".to_string(), "worker".to_string());
+        monotonic.apply(&mut [other]);
+        assert_eq!(monotonic.last.len(), 1);
+
+        let mut streams = vec![stream(&[5])];
+        monotonic.apply(&mut streams);
+        assert_eq!(timestamps(&streams[0]), vec![5]);
+    }
+}
@@ -0,0 +1,211 @@
+//! A module which contains the [`FenrirLayer`] for sending the events of the
+//! [tracing](https://crates.io/crates/tracing) crate to Loki.
+use crate::Fenrir;
+use log::Log;
+use std::fmt::Debug;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// The name of the key-value-pair which contains the names of all spans the event was recorded in
+const SPAN_KEY: &str = "span";
+
+/// The [`FenrirLayer`] implements the [`Layer`] trait of `tracing-subscriber` and sends all events
+/// to Loki using the supplied [`Fenrir`] instance (including its buffering, labels and backend).
+///
+/// The fields of the event and of all spans it was recorded in are handled like the key-value-pairs
+/// of structured logging messages (see [`crate::FenrirBuilder::key_value_mode`]), the names of the
+/// spans are added as the `span` field (e.g. `request:database`).
+///
+/// # Example
+/// ```
+/// use fenrir_rs::tracing::FenrirLayer;
+/// use fenrir_rs::{Fenrir, NetworkingBackend, SerializationFormat};
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let fenrir = Fenrir::builder()
+///     .network(NetworkingBackend::Ureq)
+///     .format(SerializationFormat::Json)
+///     .build();
+/// let subscriber = tracing_subscriber::registry().with(FenrirLayer::new(fenrir));
+/// ```
+pub struct FenrirLayer {
+    fenrir: Fenrir,
+}
+
+impl FenrirLayer {
+    /// Create a new layer which sends all events using the supplied [`Fenrir`] instance.
+    pub fn new(fenrir: Fenrir) -> FenrirLayer {
+        FenrirLayer { fenrir }
+    }
+}
+
+/// The fields recorded for a span, stored in the extensions of the span
+struct SpanFields(Vec<(String, String)>);
+
+/// A [`Visit`] implementation which collects all fields as strings, the `message` field of events
+/// is collected separately since it is used as the line of the logging message.
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: Vec<(String, String)>,
+}
+
+impl FieldVisitor {
+    /// Store the value of the supplied field (replacing an earlier value of the same field).
+    fn insert(&mut self, field: &Field, value: String) {
+        if field.name() == "message" {
+            self.message = value;
+            return;
+        }
+        self.fields.retain(|(name, _)| name != field.name());
+        self.fields.push((field.name().to_string(), value));
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.insert(field, format!("{:?}", value));
+    }
+}
+
+/// Convert the level of a `tracing` event into the corresponding level of the `log` crate.
+fn to_log_level(level: &tracing::Level) -> log::Level {
+    match *level {
+        tracing::Level::ERROR => log::Level::Error,
+        tracing::Level::WARN => log::Level::Warn,
+        tracing::Level::INFO => log::Level::Info,
+        tracing::Level::DEBUG => log::Level::Debug,
+        tracing::Level::TRACE => log::Level::Trace,
+    }
+}
+
+impl<S> Layer<S> for FenrirLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(visitor.fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut extensions = span.extensions_mut();
+            if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+                let mut visitor = FieldVisitor {
+                    message: String::new(),
+                    fields: std::mem::take(fields),
+                };
+                values.record(&mut visitor);
+                *fields = visitor.fields;
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let log_metadata = log::Metadata::builder()
+            .level(to_log_level(metadata.level()))
+            .target(metadata.target())
+            .build();
+        if !self.fenrir.enabled(&log_metadata) {
+            return;
+        }
+
+        // collect the fields of all spans (from the outermost to the innermost one, so the inner
+        // spans can override the fields of the outer ones) and the fields of the event itself
+        let mut visitor = FieldVisitor::default();
+        let mut span_names = vec![];
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                span_names.push(span.name());
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    visitor.fields.extend(fields.iter().cloned());
+                }
+            }
+        }
+        event.record(&mut visitor);
+        if !span_names.is_empty() {
+            visitor
+                .fields
+                .push((SPAN_KEY.to_string(), span_names.join(":")));
+        }
+
+        self.fenrir.log(
+            &log::Record::builder()
+                .metadata(log_metadata)
+                .args(format_args!("{}", visitor.message))
+                .module_path(metadata.module_path())
+                .file(metadata.file())
+                .line(metadata.line())
+                .key_values(&visitor.fields)
+                .build(),
+        );
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use crate::tracing::FenrirLayer;
+    use crate::{Fenrir, FenrirBackend, FenrirError, KeyValueMode, Payload, SerializationFormat};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// A backend which records the bodies of all payloads
+    #[derive(Clone, Default)]
+    struct TestBackend {
+        bodies: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl FenrirBackend for TestBackend {
+        fn send(&self, payload: Payload) -> Result<(), FenrirError> {
+            self.bodies
+                .lock()
+                .push(String::from_utf8_lossy(payload.body()).to_string());
+            Ok(())
+        }
+
+        fn internal_type(&self) -> std::any::TypeId {
+            std::any::TypeId::of::<Self>()
+        }
+    }
+
+    #[test]
+    fn events_are_sent_with_the_fields_of_the_event_and_its_spans() {
+        let backend = TestBackend::default();
+        let fenrir = Fenrir::builder()
+            .custom_backend(backend.clone())
+            .format(SerializationFormat::Json)
+            .key_value_mode(KeyValueMode::StructuredMetadata)
+            .key_value_mode_for("service", KeyValueMode::Label)
+            .build();
+        let subscriber = tracing_subscriber::registry().with(FenrirLayer::new(fenrir));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!("request", service = "api", id = 1);
+            let _request = request.enter();
+            let database = tracing::debug_span!("database", id = 2);
+            let _database = database.enter();
+            tracing::warn!(rows = 42, "slow query");
+        });
+
+        let bodies = backend.bodies.lock();
+        assert_eq!(bodies.len(), 1);
+        assert!(bodies[0].contains(r#""stream":{"service":"api"}"#));
+        assert!(
+            bodies[0].contains(r#""slow query",{"id":"2","rows":"42","span":"request:database"}"#)
+        );
+    }
+}
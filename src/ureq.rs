@@ -1,47 +1,61 @@
 //! A module which contains the implementation for the [`FenrirBackend`] trait which uses the `ureq`
 //! crate for network communication.
-use crate::{AuthenticationMethod, FenrirBackend};
+use crate::retry::parse_retry_after;
+use crate::{
+    authorization_header, AuthenticationMethod, FenrirBackend, FenrirError, Payload, RetryPolicy,
+    TokenProvider,
+};
 use std::any::TypeId;
 use url::Url;
 
 /// A [`FenrirBackend`] implementation which uses the [ureq](https://crates.io/crates/ureq) crate to
 /// send logging messages to a Loki endpoint.
 pub(crate) struct UreqBackend {
-    /// The loki endpoint which is used to send log information to
-    pub(crate) endpoint: Url,
-    /// The authentication method to use when sending the log messages to the remote [`UreqBackend::endpoint`]
+    /// The URL of the push endpoint which is used to send log information to
+    pub(crate) push_url: Url,
+    /// The authentication method to use when sending the log messages to the remote [`UreqBackend::push_url`]
     pub(crate) authentication: AuthenticationMethod,
-    /// The credentials to use to authenticate against the remote [`UreqBackend::endpoint`]
+    /// The credentials to use to authenticate against the remote [`UreqBackend::push_url`]
     pub(crate) credentials: String,
+    /// The function providing the current bearer token (instead of the static credentials)
+    pub(crate) token_provider: Option<TokenProvider>,
+    /// Additional headers which are sent with every request
+    pub(crate) extra_headers: Vec<(String, String)>,
+    /// The policy for retrying requests which failed with a transient error
+    pub(crate) retry_policy: RetryPolicy,
+    /// The agent used for all requests, which keeps the connections to the endpoint open
+    pub(crate) agent: ureq::Agent,
 }
 
 impl FenrirBackend for UreqBackend {
-    fn send(&self, serialized_streams: Vec<u8>) -> Result<(), String> {
-        use std::time::Duration;
-        use ureq::AgentBuilder;
-
-        let post_url = self
-            .endpoint
-            .clone()
-            .join("/loki/api/v1/push")
-            .map_err(|e| e.to_string())?;
-        let agent = AgentBuilder::new().timeout(Duration::from_secs(10)).build();
-        let mut request = agent.request_url("POST", &post_url);
-        request = request.set("Content-Type", "application/json; charset=utf-8");
-        match self.authentication {
-            AuthenticationMethod::None => {}
-            AuthenticationMethod::Basic => {
-                request = request.set(
-                    "Authorization",
-                    format!("Basic {}", self.credentials).as_str(),
-                );
-            }
+    fn send(&self, payload: Payload) -> Result<(), FenrirError> {
+        let mut request = self.agent.request_url("POST", &self.push_url);
+        request = request.set("Content-Type", payload.content_type());
+        if let Some(content_encoding) = payload.content_encoding() {
+            request = request.set("Content-Encoding", content_encoding);
+        }
+        if let Some(tenant) = payload.tenant() {
+            request = request.set("X-Scope-OrgID", tenant);
+        }
+        for (name, value) in &self.extra_headers {
+            request = request.set(name, value);
         }
 
-        request
-            .send_bytes(&serialized_streams)
-            .map_err(|e| e.to_string())?;
-        Ok(())
+        // a short-lived token might expire while retrying, so it is requested for every attempt
+        self.retry_policy.retry_blocking(|| {
+            let mut request = request.clone();
+            if let Some(authorization) = authorization_header(
+                &self.authentication,
+                &self.credentials,
+                self.token_provider.as_ref(),
+            )? {
+                request = request.set("Authorization", &authorization);
+            }
+            request
+                .send_bytes(payload.body())
+                .map(|_| ())
+                .map_err(map_error)
+        })
     }
 
     fn internal_type(&self) -> TypeId {
@@ -55,17 +69,52 @@ impl FenrirBackend for UreqBackend {
     }
 
     fn credentials(&self) -> Option<String> {
-        if self.credentials.len() > 0 {
+        if !self.credentials.is_empty() {
             return Some(self.credentials.clone());
         }
         None
     }
+
+    fn ignored_targets(&self) -> Vec<String> {
+        ["ureq", "rustls", "native_tls", "webpki"]
+            .iter()
+            .map(|target| target.to_string())
+            .collect()
+    }
+}
+
+/// Convert an error returned by `ureq` into the corresponding [`FenrirError`].
+fn map_error(error: ureq::Error) -> FenrirError {
+    match error {
+        ureq::Error::Status(status, response) => FenrirError::Http {
+            status,
+            retry_after: response.header("Retry-After").and_then(parse_retry_after),
+            body: response.into_string().unwrap_or_default(),
+        },
+        ureq::Error::Transport(transport) => {
+            let timed_out = std::error::Error::source(&transport)
+                .and_then(|source| source.downcast_ref::<std::io::Error>())
+                .is_some_and(|error| {
+                    matches!(
+                        error.kind(),
+                        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+                    )
+                });
+            if timed_out {
+                FenrirError::Timeout(Box::new(transport))
+            } else {
+                FenrirError::Transport(Box::new(transport))
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::ureq::UreqBackend;
-    use crate::{AuthenticationMethod, Fenrir, NetworkingBackend, SerializationFormat};
+    use crate::ureq::{map_error, UreqBackend};
+    use crate::{
+        AuthenticationMethod, Fenrir, FenrirError, NetworkingBackend, SerializationFormat,
+    };
     use std::any::{Any, TypeId};
     use url::Url;
 
@@ -85,6 +134,10 @@ mod tests {
             result.backend.internal_type(),
             TypeId::of::<UreqBackend>().type_id()
         );
+        assert!(result
+            .backend
+            .ignored_targets()
+            .contains(&"ureq".to_string()));
     }
 
     #[test]
@@ -112,4 +165,75 @@ mod tests {
             TypeId::of::<UreqBackend>().type_id()
         );
     }
+
+    #[test]
+    fn creating_a_ureq_instance_with_a_bearer_token_works_correctly() {
+        let result = Fenrir::builder()
+            .endpoint(Url::parse("https://loki.example.com").unwrap())
+            .network(NetworkingBackend::Ureq)
+            .format(SerializationFormat::Json)
+            .with_bearer_token("token")
+            .build();
+        assert_eq!(
+            result.backend.authentication_method(),
+            AuthenticationMethod::Bearer
+        );
+        assert_eq!(result.backend.credentials(), Some("token".to_string()));
+    }
+
+    #[test]
+    fn the_token_is_requested_again_for_every_attempt() {
+        use crate::{Payload, RetryPolicy};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let requested = Arc::new(AtomicUsize::new(0));
+        let counter = requested.clone();
+        let result = Fenrir::builder()
+            .endpoint(Url::parse("http://127.0.0.1:1").unwrap())
+            .network(NetworkingBackend::Ureq)
+            .format(SerializationFormat::Json)
+            .retry_policy(
+                RetryPolicy::new(3)
+                    .base_delay(Duration::from_millis(1))
+                    .jitter(false),
+            )
+            .with_token_provider(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok("token".to_string())
+            })
+            .build();
+
+        assert!(result
+            .backend
+            .send(Payload::new(b"{}".to_vec(), "application/json"))
+            .is_err());
+        assert_eq!(requested.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn rejected_requests_are_reported_with_status_code_and_body() {
+        let response =
+            ureq::Response::new(429, "Too Many Requests", "rate limit exceeded").unwrap();
+        let error = map_error(ureq::Error::Status(429, response));
+        assert!(matches!(
+            error,
+            FenrirError::Http { status: 429, ref body, retry_after: None } if body == "rate limit exceeded"
+        ));
+    }
+
+    #[test]
+    fn the_retry_after_header_of_rejected_requests_is_parsed() {
+        use std::time::Duration;
+
+        let response = "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 7\r\n\r\n"
+            .parse::<ureq::Response>()
+            .unwrap();
+        let error = map_error(ureq::Error::Status(503, response));
+        assert!(matches!(
+            error,
+            FenrirError::Http { retry_after: Some(delay), .. } if delay == Duration::from_secs(7)
+        ));
+    }
 }
@@ -0,0 +1,257 @@
+//! A module which contains the background worker which is responsible for flushing the buffered
+//! logging messages to the configured backend, without blocking the threads which are logging.
+use parking_lot::{Condvar, Mutex};
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// The state which is shared between the [`FlushWorker`] handle and its background thread.
+#[derive(Default)]
+struct WorkerState {
+    /// Set to `true` if a flush was requested before the next interval elapsed
+    flush_requested: bool,
+    /// Set to `true` if the worker should do a last flush and stop afterwards
+    shutdown: bool,
+    /// The number of flushes which were started by the background thread
+    started: u64,
+    /// The number of flushes which were completed by the background thread
+    flushes: u64,
+    /// Set to `true` if the background thread stopped (after the shutdown or because it panicked)
+    stopped: bool,
+}
+
+/// The synchronization primitives used to wake up the background thread of the [`FlushWorker`].
+#[derive(Default)]
+struct WorkerSignal {
+    state: Mutex<WorkerState>,
+    condvar: Condvar,
+    /// Notified every time the background thread completed a flush
+    flushed: Condvar,
+}
+
+/// Marks the worker as stopped when the background thread exits, even if it panicked.
+struct StoppedGuard(Arc<WorkerSignal>);
+
+impl Drop for StoppedGuard {
+    fn drop(&mut self) {
+        self.0.state.lock().stopped = true;
+        self.0.flushed.notify_all();
+    }
+}
+
+/// The [`FlushWorker`] owns a background thread which calls the supplied flush function every
+/// time the configured interval elapsed or a flush was explicitly requested.
+///
+/// Dropping the worker causes a last flush and waits for the background thread to finish.
+pub(crate) struct FlushWorker {
+    signal: Arc<WorkerSignal>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FlushWorker {
+    /// Spawn a new background thread which calls `flush` every `interval` or whenever
+    /// [`FlushWorker::request_flush`] was called. If `flush` panics, the thread keeps running.
+    pub(crate) fn spawn<F>(interval: Duration, flush: F) -> FlushWorker
+    where
+        F: Fn() + Send + 'static,
+    {
+        let signal = Arc::new(WorkerSignal::default());
+        let thread_signal = signal.clone();
+        let thread = std::thread::Builder::new()
+            .name("fenrir-flush".to_string())
+            .spawn(move || {
+                let _stopped = StoppedGuard(thread_signal.clone());
+                loop {
+                    let (shutdown, flush_number) = {
+                        let mut state = thread_signal.state.lock();
+                        if !state.flush_requested && !state.shutdown {
+                            thread_signal.condvar.wait_for(&mut state, interval);
+                        }
+                        state.flush_requested = false;
+                        state.started += 1;
+                        (state.shutdown, state.started)
+                    };
+
+                    // a panic (e.g. of a custom backend) must not stop the worker, otherwise the
+                    // messages logged afterwards would never be flushed
+                    if std::panic::catch_unwind(AssertUnwindSafe(&flush)).is_err() {
+                        eprintln!("fenrir: The background worker panicked while flushing the logs");
+                    }
+
+                    {
+                        let mut state = thread_signal.state.lock();
+                        state.flushes = flush_number;
+                        thread_signal.flushed.notify_all();
+                    }
+
+                    if shutdown {
+                        break;
+                    }
+                }
+            })
+            .expect("Could not spawn the background thread for flushing the logs");
+
+        FlushWorker {
+            signal,
+            thread: Some(thread),
+        }
+    }
+
+    /// Wake up the background thread to flush the buffered logs without waiting for the interval
+    /// to elapse.
+    pub(crate) fn request_flush(&self) {
+        let mut state = self.signal.state.lock();
+        state.flush_requested = true;
+        self.signal.condvar.notify_one();
+    }
+
+    /// Wake up the background thread to flush the buffered logs and wait until the flush was
+    /// completed or the supplied timeout elapsed.
+    pub(crate) fn request_flush_and_wait(&self, timeout: Duration) {
+        self.handle().request_flush_and_wait(timeout);
+    }
+
+    /// Get a handle for requesting flushes, which can be used independently of the worker.
+    pub(crate) fn handle(&self) -> FlushHandle {
+        FlushHandle {
+            signal: self.signal.clone(),
+        }
+    }
+
+    /// Check if the background thread of the worker is still running.
+    pub(crate) fn is_running(&self) -> bool {
+        !self.signal.state.lock().stopped
+    }
+
+    /// Check if this method is called by the background thread of the worker.
+    pub(crate) fn is_current_thread(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| thread.thread().id() == std::thread::current().id())
+    }
+}
+
+/// The [`FlushHandle`] can be used for requesting flushes of a [`FlushWorker`] from other threads,
+/// without owning the worker itself.
+#[derive(Clone)]
+pub(crate) struct FlushHandle {
+    signal: Arc<WorkerSignal>,
+}
+
+impl FlushHandle {
+    /// Wake up the background thread to flush the buffered logs and wait until the flush was
+    /// completed. A flush which was already running when this method was called is not enough,
+    /// since it might have missed the latest messages.
+    ///
+    /// Returns `false` if the supplied timeout elapsed before the flush was completed.
+    pub(crate) fn request_flush_and_wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.signal.state.lock();
+        let flush_number = state.started + 1;
+        state.flush_requested = true;
+        self.signal.condvar.notify_one();
+        while state.flushes < flush_number {
+            if state.stopped {
+                return false;
+            }
+            if self
+                .signal
+                .flushed
+                .wait_until(&mut state, deadline)
+                .timed_out()
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl Drop for FlushWorker {
+    fn drop(&mut self) {
+        {
+            let mut state = self.signal.state.lock();
+            state.shutdown = true;
+            self.signal.condvar.notify_one();
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::worker::FlushWorker;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn the_worker_flushes_after_the_interval_elapsed() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let worker_counter = counter.clone();
+        let _worker = FlushWorker::spawn(Duration::from_millis(10), move || {
+            worker_counter.fetch_add(1, Ordering::SeqCst);
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(counter.load(Ordering::SeqCst) > 0);
+    }
+
+    #[test]
+    fn the_worker_flushes_when_requested_and_on_drop() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let worker_counter = counter.clone();
+        let worker = FlushWorker::spawn(Duration::from_secs(3600), move || {
+            worker_counter.fetch_add(1, Ordering::SeqCst);
+        });
+        worker.request_flush();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+        drop(worker);
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn waiting_for_a_requested_flush_returns_after_the_flush_was_done() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let worker_counter = counter.clone();
+        let worker = FlushWorker::spawn(Duration::from_secs(3600), move || {
+            worker_counter.fetch_add(1, Ordering::SeqCst);
+        });
+        worker.request_flush_and_wait(Duration::from_secs(10));
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+        assert!(!worker.is_current_thread());
+        assert!(worker.is_running());
+    }
+
+    #[test]
+    fn waiting_for_a_stopped_worker_returns_immediately() {
+        let worker = FlushWorker::spawn(Duration::from_secs(3600), || {});
+        let handle = worker.handle();
+        drop(worker);
+
+        let started = Instant::now();
+        assert!(!handle.request_flush_and_wait(Duration::from_secs(10)));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn the_worker_keeps_running_if_a_flush_panicked() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let worker_counter = counter.clone();
+        let worker = FlushWorker::spawn(Duration::from_secs(3600), move || {
+            if worker_counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("the backend is broken");
+            }
+        });
+        assert!(worker
+            .handle()
+            .request_flush_and_wait(Duration::from_secs(10)));
+        assert!(worker
+            .handle()
+            .request_flush_and_wait(Duration::from_secs(10)));
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+    }
+}